@@ -0,0 +1,593 @@
+use std::collections::HashSet;
+use std::num::NonZeroU32;
+
+use egui::{Pos2, Vec2};
+
+use super::*;
+
+#[cfg(feature = "persistence")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// How far duplicated nodes are placed from the originals, in graph space.
+const DUPLICATE_OFFSET: Vec2 = Vec2::new(20.0, 20.0);
+
+/// A copy of a set of nodes, detached from any graph. Pasting it creates new
+/// nodes with fresh ids, along with their parameters, inline values and the
+/// connections between them. Connections to nodes that weren't copied are
+/// left out.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct GraphClipboard<NodeData, DataType, ValueType> {
+    nodes: Vec<CopiedNode<NodeData, DataType, ValueType>>,
+    connections: Vec<CopiedConnection>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+struct CopiedNode<NodeData, DataType, ValueType> {
+    label: String,
+    user_data: NodeData,
+    /// The position of the node relative to the top-left node of the copy.
+    offset: Vec2,
+    inputs: Vec<CopiedInput<DataType, ValueType>>,
+    outputs: Vec<(String, DataType)>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+struct CopiedInput<DataType, ValueType> {
+    name: String,
+    typ: DataType,
+    value: ValueType,
+    kind: InputParamKind,
+    max_connections: Option<NonZeroU32>,
+    shown_inline: bool,
+}
+
+/// A connection between copied nodes, as (node, parameter) indices.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+struct CopiedConnection {
+    output: (usize, usize),
+    input: (usize, usize),
+}
+
+impl<NodeData, DataType, ValueType> GraphClipboard<NodeData, DataType, ValueType>
+where
+    NodeData: Clone,
+    DataType: Clone,
+    ValueType: Clone,
+{
+    /// Copies `nodes` from `graph`, keeping the layout given by
+    /// `node_positions`.
+    pub fn copy(
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_positions: &SecondaryMap<NodeId, Pos2>,
+        nodes: &[NodeId],
+    ) -> Self {
+        let mut nodes: Vec<NodeId> = nodes
+            .iter()
+            .copied()
+            .filter(|node_id| graph.nodes.contains_key(*node_id))
+            .collect();
+        let mut seen = HashSet::new();
+        nodes.retain(|node_id| seen.insert(*node_id));
+        let position = |node_id| node_positions.get(node_id).copied().unwrap_or(Pos2::ZERO);
+        let origin = nodes
+            .iter()
+            .fold(Pos2::new(f32::MAX, f32::MAX), |origin, node_id| {
+                origin.min(position(*node_id))
+            });
+
+        let copied_nodes = nodes
+            .iter()
+            .map(|node_id| {
+                let node = &graph[*node_id];
+                CopiedNode {
+                    label: node.label.clone(),
+                    user_data: node.user_data.clone(),
+                    offset: position(*node_id) - origin,
+                    inputs: node
+                        .inputs
+                        .iter()
+                        .map(|(name, input_id)| {
+                            let input = &graph[*input_id];
+                            CopiedInput {
+                                name: name.clone(),
+                                typ: input.typ.clone(),
+                                value: input.value.clone(),
+                                kind: input.kind,
+                                max_connections: input.max_connections,
+                                shown_inline: input.shown_inline,
+                            }
+                        })
+                        .collect(),
+                    outputs: node
+                        .outputs
+                        .iter()
+                        .map(|(name, output_id)| (name.clone(), graph[*output_id].typ.clone()))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        // Locates a parameter among the copied nodes
+        let find = |node_id: NodeId, position: Option<usize>| {
+            Some((nodes.iter().position(|id| *id == node_id)?, position?))
+        };
+        let mut connections = vec![];
+        for (node_index, node_id) in nodes.iter().enumerate() {
+            for (input_index, (_, input_id)) in graph[*node_id].inputs.iter().enumerate() {
+                for output_id in graph.connections(*input_id) {
+                    let output_node = graph[*output_id].node;
+                    let output_position = graph[output_node]
+                        .outputs
+                        .iter()
+                        .position(|(_, id)| id == output_id);
+                    if let Some(output) = find(output_node, output_position) {
+                        connections.push(CopiedConnection {
+                            output,
+                            input: (node_index, input_index),
+                        });
+                    }
+                }
+            }
+        }
+
+        Self {
+            nodes: copied_nodes,
+            connections,
+        }
+    }
+
+    /// Adds a copy of the nodes to `graph`, with the top-left node at
+    /// `position`. Returns the new nodes and their positions.
+    pub fn paste(
+        &self,
+        graph: &mut Graph<NodeData, DataType, ValueType>,
+        position: Pos2,
+    ) -> Vec<(NodeId, Pos2)> {
+        let new_nodes: Vec<(NodeId, Pos2)> = self
+            .nodes
+            .iter()
+            .map(|copied| {
+                let node_id = graph.add_node(
+                    copied.label.clone(),
+                    copied.user_data.clone(),
+                    |graph, node_id| {
+                        for input in &copied.inputs {
+                            graph.add_wide_input_param(
+                                node_id,
+                                input.name.clone(),
+                                input.typ.clone(),
+                                input.value.clone(),
+                                input.kind,
+                                input.max_connections,
+                                input.shown_inline,
+                            );
+                        }
+                        for (name, typ) in &copied.outputs {
+                            graph.add_output_param(node_id, name.clone(), typ.clone());
+                        }
+                    },
+                );
+                (node_id, position + copied.offset)
+            })
+            .collect();
+
+        for connection in &self.connections {
+            let (output_node, output) = connection.output;
+            let (input_node, input) = connection.input;
+            let output_id = graph[new_nodes[output_node].0].outputs[output].1;
+            let input_id = graph[new_nodes[input_node].0].inputs[input].1;
+            graph.add_connection(output_id, input_id);
+        }
+        new_nodes
+    }
+}
+
+impl<NodeData, DataType, ValueType> GraphClipboard<NodeData, DataType, ValueType> {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<NodeData, DataType, ValueType> GraphClipboard<NodeData, DataType, ValueType>
+where
+    NodeData: Serialize + DeserializeOwned,
+    DataType: Serialize + DeserializeOwned,
+    ValueType: Serialize + DeserializeOwned,
+{
+    /// Serializes the clipboard with RON, so that it can go through the
+    /// system clipboard.
+    pub fn to_text(&self) -> Option<String> {
+        ron::to_string(self).ok()
+    }
+
+    /// Reads back the text written by [`GraphClipboard::to_text`].
+    pub fn from_text(text: &str) -> Option<Self> {
+        ron::from_str(text).ok()
+    }
+}
+
+/// The copy, cut and paste shortcuts received this frame.
+pub(crate) struct ClipboardInput {
+    pub copy: bool,
+    pub cut: bool,
+    pub paste: bool,
+}
+
+impl ClipboardInput {
+    /// Reads the clipboard shortcuts meant for the editor drawn in `ui`, i.e.
+    /// the ones received while the cursor is over it and no widget (e.g. a
+    /// text field) has the keyboard. Depending on the platform, these arrive
+    /// as dedicated events, key presses, or both.
+    pub fn read(ui: &egui::Ui) -> Option<Self> {
+        let cursor_pos = ui.ctx().input().pointer.hover_pos().unwrap_or(Pos2::ZERO);
+        if !ui.max_rect().contains(cursor_pos) || ui.memory().focus().is_some() {
+            return None;
+        }
+        let input = ui.input();
+        let key_command = |key| input.modifiers.command && input.key_pressed(key);
+        let pasted = input
+            .events
+            .iter()
+            .any(|event| matches!(event, egui::Event::Paste(_)));
+        Some(Self {
+            copy: key_command(egui::Key::C) || input.events.contains(&egui::Event::Copy),
+            cut: key_command(egui::Key::X) || input.events.contains(&egui::Event::Cut),
+            paste: key_command(egui::Key::V) || pasted,
+        })
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<NodeData, DataType, ValueType, NodeTemplate, UserState>
+    GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
+where
+    NodeData: Clone + Serialize + DeserializeOwned,
+    DataType: Clone + Serialize + DeserializeOwned,
+    ValueType: Clone + Serialize + DeserializeOwned,
+{
+    /// Shares copied nodes with the system clipboard, as RON text, so that
+    /// they can be pasted in another instance of the application. Call it
+    /// right before [`GraphEditorState::draw_graph_editor`], with the same
+    /// `ui`. Otherwise, the editor only copies to
+    /// [`GraphEditorState::clipboard`].
+    pub fn sync_system_clipboard(&mut self, ui: &egui::Ui) {
+        let input = match ClipboardInput::read(ui) {
+            Some(input) => input,
+            None => return,
+        };
+        if (input.copy || input.cut) && !self.selected_nodes.is_empty() {
+            let clipboard =
+                GraphClipboard::copy(&self.graph, &self.node_positions, &self.selected_nodes);
+            if let Some(text) = clipboard.to_text() {
+                ui.output().copied_text = text;
+            }
+        }
+        // Text from other applications is left alone, and the editor pastes
+        // its own clipboard instead
+        let pasted = ui.input().events.iter().find_map(|event| match event {
+            egui::Event::Paste(text) if input.paste => GraphClipboard::from_text(text),
+            _ => None,
+        });
+        if let Some(clipboard) = pasted {
+            self.clipboard = Some(clipboard);
+        }
+    }
+}
+
+impl<NodeData, DataType, ValueType, NodeTemplate, UserState>
+    GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
+where
+    NodeData:
+        NodeDataTrait<DataType = DataType, ValueType = ValueType, UserState = UserState> + Clone,
+    NodeData::Response: UserResponseTrait,
+    DataType: Clone,
+    ValueType: Clone,
+{
+    /// Copies the selected nodes to [`GraphEditorState::clipboard`]. Nothing
+    /// happens when the selection is empty.
+    pub fn copy_selection(&mut self) {
+        if !self.selected_nodes.is_empty() {
+            self.clipboard = Some(GraphClipboard::copy(
+                &self.graph,
+                &self.node_positions,
+                &self.selected_nodes,
+            ));
+        }
+    }
+
+    /// Copies the selected nodes, then deletes the ones that can be deleted.
+    pub fn cut_selection(
+        &mut self,
+        user_state: &mut UserState,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        self.copy_selection();
+        let deletable = self.deletable_selection(user_state);
+        self.delete_nodes(&deletable)
+    }
+
+    /// Deletes the selected connections, and the selected nodes for which
+    /// [`NodeDataTrait::can_delete`] returns true, as described in
+    /// [`GraphEditorState::delete_nodes_and_connections`]. The selected
+    /// comment frames are removed too.
+    pub fn delete_selection(
+        &mut self,
+        user_state: &mut UserState,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let deletable = self.deletable_selection(user_state);
+        let connections = self.selected_connections.clone();
+        let frames = std::mem::take(&mut self.selected_frames);
+        self.history.seal();
+        self.remove_frames(&frames);
+        let responses = self.remove_nodes_and_connections(&deletable, &connections);
+        self.history.seal();
+        responses
+    }
+
+    fn deletable_selection(&self, user_state: &mut UserState) -> Vec<NodeId> {
+        self.selected_nodes
+            .iter()
+            .copied()
+            .filter(|node_id| {
+                self.graph[*node_id]
+                    .user_data
+                    .can_delete(*node_id, &self.graph, user_state)
+            })
+            .collect()
+    }
+
+    /// Pastes `clipboard` with its top-left node at `position`, in graph
+    /// space. The new nodes become the selection. Returns a `CreatedNode`
+    /// response for each of them, and a `ConnectEventEnded` for each
+    /// connection between them.
+    pub fn paste(
+        &mut self,
+        clipboard: &GraphClipboard<NodeData, DataType, ValueType>,
+        position: Pos2,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        if clipboard.is_empty() {
+            return vec![];
+        }
+        let new_nodes = clipboard.paste(&mut self.graph, position);
+        self.place_pasted_nodes(new_nodes)
+    }
+
+    /// Duplicates the selected nodes, with their inline values and the
+    /// connections between them, next to the originals. When
+    /// `keep_incoming_connections` is true, the connections arriving at the
+    /// selection from nodes outside of it are duplicated too. The new nodes
+    /// become the selection. Returns the same responses as
+    /// [`GraphEditorState::paste`].
+    pub fn duplicate_selection(
+        &mut self,
+        keep_incoming_connections: bool,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut originals: Vec<NodeId> = self
+            .selected_nodes
+            .iter()
+            .copied()
+            .filter(|node_id| self.graph.nodes.contains_key(*node_id))
+            .collect();
+        let mut seen = HashSet::new();
+        originals.retain(|node_id| seen.insert(*node_id));
+        if originals.is_empty() {
+            return vec![];
+        }
+
+        let clipboard = GraphClipboard::copy(&self.graph, &self.node_positions, &originals);
+        let origin = originals
+            .iter()
+            .fold(Pos2::new(f32::MAX, f32::MAX), |origin, node_id| {
+                origin.min(
+                    self.node_positions
+                        .get(*node_id)
+                        .copied()
+                        .unwrap_or(Pos2::ZERO),
+                )
+            });
+        let new_nodes = clipboard.paste(&mut self.graph, origin + DUPLICATE_OFFSET);
+
+        if keep_incoming_connections {
+            // Both nodes have the same parameters, in the same order.
+            for (original, (duplicate, _)) in originals.iter().zip(&new_nodes) {
+                let inputs = self.graph[*original]
+                    .input_ids()
+                    .zip(self.graph[*duplicate].input_ids())
+                    .collect::<SVec<_>>();
+                for (original_input, duplicate_input) in inputs {
+                    // Inserted at their original index, so that wide inputs
+                    // keep the order of their connections.
+                    let outside = self
+                        .graph
+                        .connections(original_input)
+                        .iter()
+                        .copied()
+                        .enumerate()
+                        .filter(|(_, output)| !originals.contains(&self.graph[*output].node))
+                        .collect::<SVec<_>>();
+                    for (position, output) in outside {
+                        self.graph
+                            .insert_connection(output, duplicate_input, position);
+                    }
+                }
+            }
+        }
+        self.place_pasted_nodes(new_nodes)
+    }
+
+    /// Adds the nodes created by [`GraphClipboard::paste`] to the editor,
+    /// selects them and records the change in the history.
+    fn place_pasted_nodes(
+        &mut self,
+        new_nodes: Vec<(NodeId, Pos2)>,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut responses = vec![];
+        for (node_id, position) in new_nodes.iter().copied() {
+            self.node_positions.insert(node_id, position);
+            self.node_order.push(node_id);
+            responses.push(NodeResponse::CreatedNode(node_id));
+        }
+        for (node_id, _) in &new_nodes {
+            responses.extend(
+                self.graph
+                    .incoming_connections(*node_id)
+                    .map(|(input, output)| NodeResponse::ConnectEventEnded { output, input }),
+            );
+        }
+        let new_nodes: Vec<_> = new_nodes.into_iter().map(|(node_id, _)| node_id).collect();
+        self.record_created_nodes(&new_nodes);
+        self.selected_nodes = new_nodes;
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestGraph = Graph<u32, (), f32>;
+
+    #[test]
+    pub fn test_copy_paste() {
+        let mut graph = TestGraph::new();
+        let mut positions = SecondaryMap::new();
+        let mut add_node = |graph: &mut TestGraph, data, position| {
+            let node = graph.add_node("node".into(), data, |_, _| {});
+            let input = graph.add_input_param(
+                node,
+                "in".into(),
+                (),
+                data as f32,
+                InputParamKind::ConnectionOrConstant,
+                true,
+            );
+            let output = graph.add_output_param(node, "out".into(), ());
+            positions.insert(node, position);
+            (node, input, output)
+        };
+        let (a, a_in, a_out) = add_node(&mut graph, 1, Pos2::new(10.0, 20.0));
+        let (b, b_in, _) = add_node(&mut graph, 2, Pos2::new(50.0, 0.0));
+        let (_, _, outside_out) = add_node(&mut graph, 3, Pos2::ZERO);
+        graph.add_connection(a_out, b_in);
+        graph.add_connection(outside_out, a_in);
+
+        let clipboard = GraphClipboard::copy(&graph, &positions, &[a, b]);
+        let pasted = clipboard.paste(&mut graph, Pos2::new(100.0, 100.0));
+        assert_eq!(graph.nodes.len(), 5);
+
+        let (new_a, new_a_pos) = pasted[0];
+        let (new_b, new_b_pos) = pasted[1];
+        assert!(new_a != a && new_b != b);
+        assert_eq!(graph[new_b].user_data, 2);
+        assert_eq!(graph[graph[new_a].get_input("in").unwrap()].value, 1.0);
+        // Positions are relative to the top-left corner of the copied nodes
+        assert_eq!(new_a_pos, Pos2::new(100.0, 120.0));
+        assert_eq!(new_b_pos, Pos2::new(140.0, 100.0));
+
+        // Only the internal connection is copied
+        let new_a_in = graph[new_a].get_input("in").unwrap();
+        let new_b_in = graph[new_b].get_input("in").unwrap();
+        assert!(graph.connections(new_a_in).is_empty());
+        assert_eq!(
+            graph.connections(new_b_in),
+            &[graph[new_a].get_output("out").unwrap()]
+        );
+    }
+
+    #[test]
+    pub fn test_duplicate_selection() {
+        use crate::history::tests::{create_node, TestState};
+
+        let mut state = TestState::default();
+        let (_, _, outside_out) = create_node(&mut state, Pos2::ZERO);
+        let (a, a_in, a_out) = create_node(&mut state, Pos2::new(100.0, 0.0));
+        let (b, b_in, _) = create_node(&mut state, Pos2::new(200.0, 0.0));
+        state.graph.add_connection(outside_out, a_in);
+        state.graph.add_connection(a_out, b_in);
+        state.graph[a_in].value = 5.0;
+
+        state.selected_nodes = vec![a, b];
+        let responses = state.duplicate_selection(false);
+        let (new_a, new_b) = (state.selected_nodes[0], state.selected_nodes[1]);
+        assert!(new_a != a && new_b != b);
+        assert_eq!(state.node_positions[new_a], Pos2::new(120.0, 20.0));
+        assert_eq!(state.node_order.len(), 5);
+        let new_a_in = state.graph[new_a].get_input("in").unwrap();
+        assert_eq!(state.graph[new_a_in].value, 5.0);
+        assert!(state.graph.connections(new_a_in).is_empty());
+        let created = responses
+            .iter()
+            .filter(|response| matches!(response, NodeResponse::CreatedNode(_)))
+            .count();
+        assert_eq!(created, 2);
+
+        // The duplicate of `a` can also keep its connection from outside
+        state.selected_nodes = vec![a, b];
+        state.duplicate_selection(true);
+        let new_a = state.selected_nodes[0];
+        let new_a_in = state.graph[new_a].get_input("in").unwrap();
+        assert_eq!(state.graph.connections(new_a_in), &[outside_out]);
+
+        // Duplicating is undone in one step
+        state.undo();
+        assert_eq!(state.graph.nodes.len(), 5);
+    }
+
+    #[test]
+    pub fn test_delete_selection() {
+        use crate::history::tests::{create_node, TestState};
+
+        let mut state = TestState::default();
+        let (a, _, a_out) = create_node(&mut state, Pos2::ZERO);
+        let (b, b_in, _) = create_node(&mut state, Pos2::new(100.0, 0.0));
+        let (c, _, _) = create_node(&mut state, Pos2::new(200.0, 0.0));
+        state.graph.add_connection(a_out, b_in);
+
+        // Deleting a selected connection doesn't start a new one
+        state.selected_connections = vec![(b_in, a_out)];
+        let responses = state.delete_selection(&mut ());
+        assert!(matches!(
+            responses.as_slice(),
+            [NodeResponse::DisconnectEvent { input, output }] if *input == b_in && *output == a_out
+        ));
+        assert!(state.graph.connections(b_in).is_empty());
+        assert!(state.selected_connections.is_empty());
+        assert!(state.connection_in_progress.is_none());
+        state.undo();
+        assert_eq!(state.graph.connections(b_in), &[a_out]);
+
+        state.selected_nodes = vec![a, b];
+        let responses = state.delete_selection(&mut ());
+        assert!(matches!(
+            responses.as_slice(),
+            [
+                NodeResponse::DisconnectEvent { .. },
+                NodeResponse::DeleteNodeFull { node_id: first, .. },
+                NodeResponse::DeleteNodeFull { node_id: second, .. },
+            ] if *first == a && *second == b
+        ));
+        assert_eq!(state.node_order, vec![c]);
+        assert!(state.selected_nodes.is_empty());
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    pub fn test_clipboard_text() {
+        let mut graph = TestGraph::new();
+        let node = graph.add_node("node".into(), 7, |_, _| {});
+        let mut positions = SecondaryMap::new();
+        positions.insert(node, Pos2::ZERO);
+
+        let text = GraphClipboard::copy(&graph, &positions, &[node])
+            .to_text()
+            .unwrap();
+        let clipboard = GraphClipboard::<u32, (), f32>::from_text(&text).unwrap();
+        let pasted = clipboard.paste(&mut graph, Pos2::ZERO);
+        assert_eq!(graph[pasted[0].0].user_data, 7);
+        assert!(GraphClipboard::<u32, (), f32>::from_text("not a graph").is_none());
+    }
+}
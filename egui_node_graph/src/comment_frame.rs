@@ -0,0 +1,65 @@
+use egui::{Color32, Rect, Vec2};
+
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+
+/// The height of the title bar of comment frames, in graph units.
+pub const FRAME_TITLE_HEIGHT: f32 = 24.0;
+/// The space left between a frame and the nodes it's created around.
+const FRAME_PADDING: f32 = 20.0;
+/// The smallest size frames can be resized to.
+pub const FRAME_MIN_SIZE: Vec2 = Vec2::new(100.0, 60.0);
+
+/// A titled, coloured rectangle drawn behind the nodes to group them, like
+/// the backdrops of compositing tools. Dragging its title bar moves it along
+/// with the nodes inside of it, dragging its bottom-right corner resizes it,
+/// and double-clicking its title bar edits the title. Frames have no effect on
+/// the graph itself.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct CommentFrame {
+    pub title: String,
+    /// The area covered by the frame, title bar included, in graph space.
+    pub rect: Rect,
+    pub color: Color32,
+}
+
+impl CommentFrame {
+    pub fn new(title: String, rect: Rect, color: Color32) -> Self {
+        Self { title, rect, color }
+    }
+
+    /// Creates a frame surrounding `bounds`, given in graph space, with some
+    /// padding and room for the title bar.
+    pub fn around(title: String, bounds: Rect, color: Color32) -> Self {
+        let rect = Rect::from_min_max(
+            bounds.min - Vec2::new(FRAME_PADDING, FRAME_PADDING + FRAME_TITLE_HEIGHT),
+            bounds.max + Vec2::splat(FRAME_PADDING),
+        );
+        Self::new(title, rect, color)
+    }
+
+    /// Returns the area of the title bar, in graph space.
+    pub fn title_rect(&self) -> Rect {
+        Rect::from_min_size(
+            self.rect.min,
+            Vec2::new(self.rect.width(), FRAME_TITLE_HEIGHT),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::Pos2;
+
+    #[test]
+    pub fn test_frame_around_nodes() {
+        let bounds = Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(200.0, 100.0));
+        let frame = CommentFrame::around("Group".into(), bounds, Color32::RED);
+        assert!(frame.rect.contains_rect(bounds));
+        // The title bar doesn't cover the nodes
+        assert!(!frame.title_rect().intersects(bounds));
+        assert_eq!(frame.title_rect().min, frame.rect.min);
+    }
+}
@@ -0,0 +1,145 @@
+use egui::epaint::CubicBezierShape;
+use egui::{Color32, Pos2, Stroke, Vec2};
+
+/// The maximum distance, in screen units, between the curve of a connection
+/// and the line segments approximating it.
+const FLATTEN_TOLERANCE: f32 = 0.5;
+
+/// Returns the control points of the curve drawn for a connection going from
+/// `src_pos` to `dst_pos`.
+pub fn connection_points(src_pos: Pos2, dst_pos: Pos2, zoom: f32) -> [Pos2; 4] {
+    let control_scale = ((dst_pos.x - src_pos.x) / 2.0).max(30.0 * zoom);
+    let src_control = src_pos + Vec2::X * control_scale;
+    let dst_control = dst_pos - Vec2::X * control_scale;
+    [src_pos, src_control, dst_control, dst_pos]
+}
+
+/// Approximates the cubic bezier curve with control `points` by a polyline.
+pub fn flatten(points: [Pos2; 4]) -> Vec<Pos2> {
+    CubicBezierShape::from_points_stroke(points, false, Color32::TRANSPARENT, Stroke::none())
+        .flatten(Some(FLATTEN_TOLERANCE))
+}
+
+/// Returns the distance from `pos` to the closest point of `polyline`.
+pub fn distance_to_polyline(polyline: &[Pos2], pos: Pos2) -> f32 {
+    match polyline {
+        [] => f32::INFINITY,
+        [point] => point.distance(pos),
+        _ => polyline
+            .windows(2)
+            .map(|segment| distance_to_segment(segment[0], segment[1], pos))
+            .fold(f32::INFINITY, f32::min),
+    }
+}
+
+/// Returns true if the segment from `a` to `b` crosses `polyline`.
+fn segment_intersects_polyline(a: Pos2, b: Pos2, polyline: &[Pos2]) -> bool {
+    polyline
+        .windows(2)
+        .any(|segment| segments_intersect(a, b, segment[0], segment[1]))
+}
+
+/// Returns true if the segment from `a` to `b` crosses the cubic bezier curve
+/// with control `points`.
+pub fn segment_intersects_bezier(a: Pos2, b: Pos2, points: [Pos2; 4]) -> bool {
+    segment_intersects_polyline(a, b, &flatten(points))
+}
+
+/// Returns true if the segments `a1`-`a2` and `b1`-`b2` have a point in
+/// common, including when they only touch.
+fn segments_intersect(a1: Pos2, a2: Pos2, b1: Pos2, b2: Pos2) -> bool {
+    // The sign of the cross product tells on which side of the line through
+    // `p` and `q` the point `r` lies.
+    fn side(p: Pos2, q: Pos2, r: Pos2) -> f32 {
+        (q - p).x * (r - p).y - (q - p).y * (r - p).x
+    }
+    // Whether `r`, known to be on the line through `p` and `q`, is between them
+    fn on_segment(p: Pos2, q: Pos2, r: Pos2) -> bool {
+        r.x >= p.x.min(q.x) && r.x <= p.x.max(q.x) && r.y >= p.y.min(q.y) && r.y <= p.y.max(q.y)
+    }
+
+    let d1 = side(b1, b2, a1);
+    let d2 = side(b1, b2, a2);
+    let d3 = side(a1, a2, b1);
+    let d4 = side(a1, a2, b2);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(b1, b2, a1))
+        || (d2 == 0.0 && on_segment(b1, b2, a2))
+        || (d3 == 0.0 && on_segment(a1, a2, b1))
+        || (d4 == 0.0 && on_segment(a1, a2, b2))
+}
+
+fn distance_to_segment(a: Pos2, b: Pos2, pos: Pos2) -> f32 {
+    let ab = b - a;
+    let length_sq = ab.length_sq();
+    if length_sq == 0.0 {
+        return a.distance(pos);
+    }
+    let t = ((pos - a).dot(ab) / length_sq).clamp(0.0, 1.0);
+    (a + ab * t).distance(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_distance_to_connection() {
+        let src = Pos2::new(0.0, 0.0);
+        let dst = Pos2::new(200.0, 100.0);
+        let polyline = flatten(connection_points(src, dst, 1.0));
+        assert_eq!(polyline.first(), Some(&src));
+        assert_eq!(polyline.last(), Some(&dst));
+
+        // The curve is symmetric around its middle point
+        assert!(distance_to_polyline(&polyline, Pos2::new(100.0, 50.0)) < 1.0);
+        assert!(distance_to_polyline(&polyline, Pos2::new(100.0, 60.0)) > 5.0);
+        // Past the ends, the distance is the one to the closest end
+        let beyond = distance_to_polyline(&polyline, Pos2::new(-10.0, 0.0));
+        assert!((beyond - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    pub fn test_segment_intersects_bezier() {
+        let points = connection_points(Pos2::new(0.0, 0.0), Pos2::new(200.0, 100.0), 1.0);
+
+        // A vertical cut through the middle of the curve
+        let (top, bottom) = (Pos2::new(100.0, -50.0), Pos2::new(100.0, 150.0));
+        assert!(segment_intersects_bezier(top, bottom, points));
+        assert!(segment_intersects_bezier(bottom, top, points));
+        // A cut stopping short of the curve
+        assert!(!segment_intersects_bezier(
+            top,
+            Pos2::new(100.0, 40.0),
+            points
+        ));
+        // Cuts past the ends of the curve
+        assert!(!segment_intersects_bezier(
+            Pos2::new(-10.0, -50.0),
+            Pos2::new(-10.0, 50.0),
+            points
+        ));
+        assert!(!segment_intersects_bezier(
+            Pos2::new(210.0, 50.0),
+            Pos2::new(210.0, 150.0),
+            points
+        ));
+
+        // Touching segments intersect, parallel ones don't
+        let (a, b) = (Pos2::new(0.0, 0.0), Pos2::new(10.0, 0.0));
+        assert!(segments_intersect(
+            a,
+            b,
+            Pos2::new(10.0, 0.0),
+            Pos2::new(10.0, 5.0)
+        ));
+        assert!(!segments_intersect(
+            a,
+            b,
+            Pos2::new(0.0, 1.0),
+            Pos2::new(10.0, 1.0)
+        ));
+    }
+}
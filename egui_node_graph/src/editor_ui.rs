@@ -1,6 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::color_hex_utils::*;
+use crate::curves;
+use crate::minimap::{self, MinimapTransform};
+use crate::scale::Scale;
 use crate::utils::ColorUtils;
 
 use super::*;
@@ -11,6 +13,30 @@ pub type PortLocations = std::collections::HashMap<AnyParameterId, Pos2>;
 pub type NodeRects = std::collections::HashMap<NodeId, Rect>;
 
 const DISTANCE_TO_CONNECT: f32 = 10.0;
+/// The distance between the connection slots of inputs accepting multiple
+/// connections.
+const PORT_SLOT_SPACING: f32 = 12.0;
+/// The radius of the reroute knots of connections.
+const KNOT_RADIUS: f32 = 5.0;
+/// The size of the handle resizing comment frames, at their bottom-right
+/// corner.
+const FRAME_RESIZE_HANDLE_SIZE: f32 = 12.0;
+/// The smallest distance between the lines of the grid, in screen units.
+/// When zoomed out further, only every other line is drawn.
+const MIN_GRID_SPACING: f32 = 8.0;
+/// The size of the minimap, in screen units.
+const MINIMAP_SIZE: Vec2 = Vec2::new(200.0, 150.0);
+/// The space between the minimap and the corner of the editor.
+const MINIMAP_MARGIN: f32 = 10.0;
+/// How fast the mouse wheel zooms the graph. Matches egui's ctrl+scroll speed.
+const SCROLL_ZOOM_SPEED: f32 = 1.0 / 200.0;
+
+/// The width reserved in the title bar for the evaluation state of a node.
+const EVALUATION_STATE_SIZE: f32 = 12.0;
+/// The size of the handle resizing nodes, at their bottom-right corner.
+const NODE_RESIZE_HANDLE_SIZE: f32 = 10.0;
+/// The width reserved in the title bar for the button collapsing a node.
+const COLLAPSE_TOGGLE_SIZE: f32 = 12.0;
 
 /// Nodes communicate certain events to the parent graph when drawn. There is
 /// one special `User` variant which can be used by users as the return value
@@ -38,8 +64,30 @@ pub enum NodeResponse<UserResponse: UserResponseTrait, NodeData: NodeDataTrait>
         output: OutputId,
         input: InputId,
     },
+    /// Emitted when the inline value of an input was changed by its
+    /// `value_widget`.
+    ValueChanged {
+        node: NodeId,
+        input: InputId,
+    },
+    /// Emitted when the title bar toggle of a node is clicked. `collapsed` is
+    /// the new state of the node.
+    CollapseNode {
+        node: NodeId,
+        collapsed: bool,
+    },
+    /// Emitted when a node is resized with the handle on its bottom-right
+    /// corner. The `size` is given in graph space, and already satisfies the
+    /// [`NodeSizeConstraints`] of the node.
+    ResizeNode {
+        node: NodeId,
+        size: Vec2,
+    },
     /// Emitted when a node is interacted with, and should be raised
     RaiseNode(NodeId),
+    /// Emitted when a node is dragged. The `drag_delta` is given in graph
+    /// space, i.e. it is independent of the current zoom level. When nodes
+    /// snap to the grid, it is the distance the node actually moved.
     MoveNode {
         node: NodeId,
         drag_delta: Vec2,
@@ -72,6 +120,36 @@ impl<UserResponse: UserResponseTrait, NodeData: NodeDataTrait> Default
         }
     }
 }
+/// The sizes a node can take, in graph units, as returned by
+/// [`NodeDataTrait::size_constraints`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeSizeConstraints {
+    /// The smallest size the user can shrink the node to.
+    pub min: Vec2,
+    /// The largest size the user can grow the node to.
+    pub max: Vec2,
+    /// The space given to the contents of a node that hasn't been resized by
+    /// the user. The node shrinks to fit its contents when they are smaller.
+    pub default: Vec2,
+}
+
+impl Default for NodeSizeConstraints {
+    fn default() -> Self {
+        Self {
+            min: vec2(80.0, 40.0),
+            max: vec2(800.0, 800.0),
+            default: vec2(200.0, 200.0),
+        }
+    }
+}
+
+impl NodeSizeConstraints {
+    /// Returns `size` clamped between the smallest and largest sizes.
+    pub fn clamp(&self, size: Vec2) -> Vec2 {
+        size.max(self.min).min(self.max)
+    }
+}
+
 pub struct GraphNodeWidget<'a, NodeData, DataType, ValueType> {
     pub position: &'a mut Pos2,
     pub graph: &'a mut Graph<NodeData, DataType, ValueType>,
@@ -79,29 +157,47 @@ pub struct GraphNodeWidget<'a, NodeData, DataType, ValueType> {
     pub node_rects: &'a mut NodeRects,
     pub node_id: NodeId,
     pub ongoing_drag: Option<(NodeId, AnyParameterId)>,
+    /// When true, dropping a connection that would create a cycle is refused.
+    pub reject_cycles: bool,
     pub selected: bool,
+    /// The screen offset of the graph origin. Includes the editor position.
     pub pan: egui::Vec2,
+    /// The zoom level of the editor. Node contents are scaled by this factor.
+    pub zoom: f32,
+    /// The evaluation state to show in the title bar, if any.
+    pub evaluation_state: Option<NodeEvaluationState>,
+    /// When true, only the title bar of the node is drawn, with all of its
+    /// ports stacked at its edges.
+    pub collapsed: bool,
+    /// The size of the node set by the user, in graph space. When `None`, the
+    /// node fits its contents.
+    pub size: Option<Vec2>,
+    pub style: &'a NodeGraphStyle,
+    /// Gets filled with the previous value of the inline values changed while
+    /// drawing the node.
+    pub edited_values: &'a mut Vec<(InputId, ValueType)>,
 }
 
 impl<NodeData, DataType, ValueType, NodeTemplate, UserResponse, UserState>
     GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
 where
     NodeData: NodeDataTrait<
-        Response = UserResponse,
-        UserState = UserState,
-        DataType = DataType,
-        ValueType = ValueType,
-    >,
+            Response = UserResponse,
+            UserState = UserState,
+            DataType = DataType,
+            ValueType = ValueType,
+        > + Clone,
     UserResponse: UserResponseTrait,
-    ValueType:
-        WidgetValueTrait<Response = UserResponse, UserState = UserState, NodeData = NodeData>,
+    ValueType: WidgetValueTrait<Response = UserResponse, UserState = UserState, NodeData = NodeData>
+        + Clone
+        + PartialEq,
     NodeTemplate: NodeTemplateTrait<
         NodeData = NodeData,
         DataType = DataType,
         ValueType = ValueType,
         UserState = UserState,
     >,
-    DataType: DataTypeTrait<UserState>,
+    DataType: DataTypeTrait<UserState> + Clone,
 {
     #[must_use]
     pub fn draw_graph_editor(
@@ -120,6 +216,12 @@ where
         let mut cursor_in_editor = editor_rect.contains(cursor_pos);
         let mut cursor_in_finder = false;
 
+        self.pan_zoom.zoom = self.pan_zoom.zoom.clamp(self.zoom_min, self.zoom_max);
+        let style = self
+            .style
+            .clone()
+            .unwrap_or_else(|| NodeGraphStyle::from_visuals(ui.visuals()));
+
         // Gets filled with the node metrics as they are drawn
         let mut port_locations = PortLocations::new();
         let mut node_rects = NodeRects::new();
@@ -128,6 +230,116 @@ where
         // executed at the end of this function.
         let mut delayed_responses: Vec<NodeResponse<UserResponse, NodeData>> = vec![];
 
+        // Some responses generate additional responses when processed. These
+        // are stored here to report them back to the user.
+        let mut extra_responses: Vec<NodeResponse<UserResponse, NodeData>> = Vec::new();
+
+        // The previous values of the inline values edited this frame
+        let mut edited_values = vec![];
+
+        // User code may have removed selected connections since last frame
+        let graph = &self.graph;
+        self.selected_connections
+            .retain(|(input, output)| graph.connections(*input).contains(output));
+        let frames = &self.frames;
+        self.selected_frames
+            .retain(|frame_id| frames.contains_key(*frame_id));
+
+        // A gesture also starts when a mouse button is pressed or the keyboard
+        // moves to another widget, so that e.g. editing two values in a row
+        // isn't undone at once.
+        let focus = ui.memory().focus();
+        let focus_id = ui.id().with("history_focus");
+        let focus_changed = ui.memory().data.get_temp::<Option<Id>>(focus_id) != Some(focus);
+        ui.memory().data.insert_temp(focus_id, focus);
+        let pointer_pressed = ui.ctx().input().pointer.any_pressed();
+        if focus_changed || pointer_pressed {
+            self.history.seal();
+        }
+        // Pressing a button or a key over the editor stops the layout
+        // animation, which records its moves before the edit that follows.
+        let key_pressed = ui
+            .input()
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::Key { pressed: true, .. }));
+        if cursor_in_editor && (pointer_pressed || key_pressed) {
+            self.stop_layout_animation();
+        }
+
+        // Undo, redo, duplicate, delete, framing and alignment, unless a
+        // widget (e.g. a text field) has the keyboard
+        let mut frame_selection = false;
+        if cursor_in_editor && ui.memory().focus().is_none() {
+            let mut input = ui.input_mut();
+            if input.consume_key(Modifiers::COMMAND, Key::Z) {
+                drop(input);
+                extra_responses.extend(self.undo());
+            } else if input.consume_key(Modifiers::COMMAND, Key::Y)
+                || input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z)
+            {
+                drop(input);
+                extra_responses.extend(self.redo());
+            } else if input.consume_key(Modifiers::COMMAND, Key::D) {
+                drop(input);
+                extra_responses.extend(self.duplicate_selection(self.duplicate_keeps_connections));
+            } else if input.consume_key(Modifiers::NONE, Key::Delete)
+                || input.consume_key(Modifiers::NONE, Key::Backspace)
+            {
+                drop(input);
+                extra_responses.extend(self.delete_selection(user_state));
+            } else if input.consume_key(Modifiers::COMMAND, Key::G) {
+                frame_selection = true;
+            } else if self.alignment_shortcuts {
+                let alignment = [
+                    (Key::ArrowLeft, Alignment::Left),
+                    (Key::ArrowRight, Alignment::Right),
+                    (Key::ArrowUp, Alignment::Top),
+                    (Key::ArrowDown, Alignment::Bottom),
+                    (Key::C, Alignment::CenterX),
+                    (Key::M, Alignment::CenterY),
+                ]
+                .into_iter()
+                .find(|(key, _)| input.consume_key(Modifiers::ALT, *key));
+                let distribution = [
+                    (Key::H, Distribution::Horizontal),
+                    (Key::V, Distribution::Vertical),
+                ]
+                .into_iter()
+                .find(|(key, _)| input.consume_key(Modifiers::ALT, *key));
+                drop(input);
+                if let Some((_, alignment)) = alignment {
+                    extra_responses.extend(self.align_selection(alignment));
+                }
+                if let Some((_, distribution)) = distribution {
+                    extra_responses.extend(self.distribute_selection(distribution));
+                }
+            }
+        }
+
+        // Copy, cut and paste, with the in-memory clipboard. See
+        // `sync_system_clipboard` for the system one.
+        if let Some(input) = ClipboardInput::read(ui) {
+            if input.cut && !self.selected_nodes.is_empty() {
+                extra_responses.extend(self.cut_selection(user_state));
+            } else if input.copy {
+                self.copy_selection();
+            }
+            if input.paste {
+                if let Some(clipboard) = self.clipboard.clone() {
+                    let position = self.pan_zoom.screen_to_graph(editor_rect.min, cursor_pos);
+                    extra_responses.extend(self.paste(&clipboard, position));
+                }
+            }
+        }
+
+        // The animated layout moves the nodes before they are drawn, and keeps
+        // the editor repainting until it stops
+        if self.layout_animation.is_some() {
+            extra_responses.extend(self.step_layout_animation());
+            ui.ctx().request_repaint();
+        }
+
         // Used to detect when the background was clicked
         let mut click_on_background = false;
 
@@ -142,6 +354,42 @@ where
         inconsistent self. It has either more or less values than the graph."
         );
 
+        // The minimap is interacted with before the nodes, so that it gets the
+        // clicks and drags over them. It is painted over everything else.
+        let minimap_rect = Rect::from_min_size(
+            editor_rect.max - MINIMAP_SIZE - Vec2::splat(MINIMAP_MARGIN),
+            MINIMAP_SIZE,
+        );
+        let minimap_response = if self.show_minimap {
+            Some(ui.interact(
+                minimap_rect,
+                ui.id().with("minimap"),
+                Sense::click_and_drag(),
+            ))
+        } else {
+            None
+        };
+        let cursor_in_minimap = self.show_minimap && minimap_rect.contains(cursor_pos);
+
+        /* Draw the background grid */
+        if self.show_grid && self.grid_spacing > 0.0 {
+            draw_grid(
+                ui.painter(),
+                editor_rect,
+                self.pan_zoom,
+                self.grid_spacing,
+                style.grid,
+            );
+        }
+
+        // Comment frames are drawn behind the nodes, but painted once the
+        // nodes are drawn, since moving a frame moves the nodes inside it.
+        let frame_shapes: Vec<_> = self
+            .frames
+            .keys()
+            .map(|frame_id| (frame_id, ui.painter().add(Shape::Noop)))
+            .collect();
+
         /* Draw nodes */
         for node_id in self.node_order.iter().copied() {
             let responses = GraphNodeWidget {
@@ -151,17 +399,271 @@ where
                 node_rects: &mut node_rects,
                 node_id,
                 ongoing_drag: self.connection_in_progress,
-                selected: self
-                    .selected_nodes
-                    .iter()
-                    .any(|selected| *selected == node_id),
+                reject_cycles: self.reject_cycles,
+                selected: self.selected_nodes.contains(&node_id),
                 pan: self.pan_zoom.pan + editor_rect.min.to_vec2(),
+                zoom: self.pan_zoom.zoom,
+                evaluation_state: self.node_evaluation_states.get(node_id).copied(),
+                collapsed: self.collapsed_nodes.contains_key(node_id),
+                size: self.node_sizes.get(node_id).copied(),
+                style: &style,
+                edited_values: &mut edited_values,
             }
             .show(ui, user_state);
 
             // Actions executed later
             delayed_responses.extend(responses);
         }
+        let cursor_over_node = node_rects.values().any(|rect| rect.contains(cursor_pos));
+        let pan_zoom = self.pan_zoom;
+        self.node_rects = node_rects
+            .iter()
+            .map(|(node_id, rect)| {
+                let min = pan_zoom.screen_to_graph(editor_rect.min, rect.min);
+                let max = pan_zoom.screen_to_graph(editor_rect.min, rect.max);
+                (*node_id, Rect::from_min_max(min, max))
+            })
+            .collect();
+
+        // Reroute knots are interacted with before the background, so they
+        // get the clicks and drags first.
+        let graph = &self.graph;
+        self.reroute_knots
+            .retain(|input, output| graph.connections(input).contains(&output));
+        let mut wires = vec![];
+        for (input, outputs) in self.graph.connections.iter() {
+            let input_pos = port_locations[&AnyParameterId::Input(input)];
+            let slot_count = input_slot_count(&self.graph, input);
+            for (slot, output) in outputs.iter().enumerate() {
+                let knots_before = self.reroute_knots.get(input, *output).to_vec();
+                let mut removed_knot = None;
+                for (index, knot) in self
+                    .reroute_knots
+                    .get_mut(input, *output)
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                {
+                    let knot_pos = self.pan_zoom.graph_to_screen(editor_rect.min, *knot);
+                    let knot_response = ui.interact(
+                        Rect::from_center_size(
+                            knot_pos,
+                            Vec2::splat(2.0 * KNOT_RADIUS * self.pan_zoom.zoom),
+                        ),
+                        ui.id().with(("reroute_knot", input, *output, index)),
+                        Sense::click_and_drag(),
+                    );
+                    if knot_response.double_clicked() {
+                        removed_knot = Some(index);
+                    } else if knot_response.dragged() {
+                        *knot += knot_response.drag_delta() / self.pan_zoom.zoom;
+                    }
+                }
+                if let Some(index) = removed_knot {
+                    self.reroute_knots.remove(input, *output, index);
+                }
+                if knots_before != self.reroute_knots.get(input, *output) {
+                    self.history.push(EditorCommand::SetKnots {
+                        input,
+                        output: *output,
+                        before: knots_before,
+                        after: self.reroute_knots.get(input, *output).to_vec(),
+                    });
+                }
+
+                let knots = self
+                    .reroute_knots
+                    .get(input, *output)
+                    .iter()
+                    .map(|knot| self.pan_zoom.graph_to_screen(editor_rect.min, *knot))
+                    .collect();
+                wires.push(DrawnConnection::new(
+                    (input, *output),
+                    port_locations[&AnyParameterId::Output(*output)],
+                    knots,
+                    slot_pos(input_pos, slot, slot_count, self.pan_zoom.zoom),
+                    self.pan_zoom.zoom,
+                ));
+            }
+        }
+
+        /* Draw comment frames */
+        let to_screen = |rect: Rect| {
+            Rect::from_min_max(
+                pan_zoom.graph_to_screen(editor_rect.min, rect.min),
+                pan_zoom.graph_to_screen(editor_rect.min, rect.max),
+            )
+        };
+        if frame_selection {
+            let bounds = self
+                .selected_nodes
+                .iter()
+                .filter_map(|node_id| node_rects.get(node_id))
+                .fold(Rect::NOTHING, |bounds, rect| bounds.union(*rect));
+            if bounds.is_positive() {
+                let bounds = Rect::from_min_max(
+                    pan_zoom.screen_to_graph(editor_rect.min, bounds.min),
+                    pan_zoom.screen_to_graph(editor_rect.min, bounds.max),
+                );
+                let data = CommentFrame::around("Frame".into(), bounds, style.frame);
+                let frame_id = self.frames.insert(data.clone());
+                self.history.seal();
+                self.history.push(EditorCommand::ChangeFrames {
+                    created: vec![FrameRecord::new(frame_id, data)],
+                    deleted: vec![],
+                });
+                self.history.seal();
+            }
+        }
+        // The frame whose title is being edited, after a double click
+        let editing_title_id = ui.id().with("editing_frame_title");
+        let editing_title: Option<FrameId> = ui.memory().data.get_temp(editing_title_id);
+        for (frame_id, frame_shape) in frame_shapes {
+            let zoom = pan_zoom.zoom;
+            let frame_ui_id = ui.id().with(("comment_frame", frame_id));
+            let frame_rect = to_screen(self.frames[frame_id].rect);
+            let title_response = ui.interact(
+                to_screen(self.frames[frame_id].title_rect()),
+                frame_ui_id,
+                Sense::click_and_drag(),
+            );
+            let handle_size = Vec2::splat(FRAME_RESIZE_HANDLE_SIZE * zoom);
+            let resize_response = ui.interact(
+                Rect::from_min_size(frame_rect.max - handle_size, handle_size),
+                frame_ui_id.with("resize"),
+                Sense::drag(),
+            );
+
+            if title_response.clicked() || title_response.drag_started() {
+                self.selected_nodes.clear();
+                self.selected_connections.clear();
+                self.selected_frames = vec![frame_id];
+            }
+            // The nodes moving with the frame are the ones inside of it when
+            // the drag starts, so that it doesn't pick up others on the way.
+            if title_response.drag_started() {
+                let contained: Vec<NodeId> = node_rects
+                    .iter()
+                    .filter(|(_, rect)| frame_rect.contains_rect(**rect))
+                    .map(|(node_id, _)| *node_id)
+                    .collect();
+                ui.memory().data.insert_temp(frame_ui_id, contained);
+            }
+            if title_response.dragged() {
+                let delta = title_response.drag_delta() / zoom;
+                let frame = &mut self.frames[frame_id];
+                frame.rect = frame.rect.translate(delta);
+                let mut contained: Vec<NodeId> =
+                    ui.memory().data.get_temp(frame_ui_id).unwrap_or_default();
+                contained.retain(|node_id| self.node_positions.contains_key(*node_id));
+                for node_id in contained.iter().copied() {
+                    self.node_positions[node_id] += delta;
+                    extra_responses.push(NodeResponse::MoveNode {
+                        node: node_id,
+                        drag_delta: delta,
+                    });
+                }
+                self.history.push(EditorCommand::MoveFrame {
+                    frame: frame_id,
+                    nodes: contained,
+                    delta,
+                });
+            }
+            if resize_response.dragged() {
+                let frame = &mut self.frames[frame_id];
+                let before = frame.rect;
+                frame.rect.max += resize_response.drag_delta() / zoom;
+                frame.rect.max = frame.rect.max.max(frame.rect.min + FRAME_MIN_SIZE);
+                if frame.rect != before {
+                    self.history.push(EditorCommand::ResizeFrame {
+                        frame: frame_id,
+                        before,
+                        after: frame.rect,
+                    });
+                }
+            }
+
+            let frame = &self.frames[frame_id];
+            let frame_rect = to_screen(frame.rect);
+            let title_rect = to_screen(frame.title_rect());
+            let rounding = Rounding::same(4.0 * zoom);
+            let stroke = if self.selected_frames.contains(&frame_id) {
+                Stroke::new(2.0 * zoom, style.selection_outline)
+            } else {
+                Stroke::none()
+            };
+            let mut font = TextStyle::Button.resolve(ui.style());
+            font.size *= zoom;
+            let title_pos = title_rect.left_center() + vec2(8.0 * zoom, 0.0);
+
+            let editing = editing_title == Some(frame_id) || title_response.double_clicked();
+            if editing {
+                let text_rect = Rect::from_min_max(
+                    pos2(title_pos.x, title_rect.min.y),
+                    title_rect.max - vec2(8.0 * zoom, 0.0),
+                );
+                let title = &mut self.frames[frame_id].title;
+                let before = title.clone();
+                let text_response = ui.put(
+                    text_rect,
+                    TextEdit::singleline(title)
+                        .font(font.clone())
+                        .text_color(style.frame_title_text)
+                        .frame(false),
+                );
+                if text_response.changed() {
+                    self.history.push(EditorCommand::RetitleFrame {
+                        frame: frame_id,
+                        before,
+                        after: self.frames[frame_id].title.clone(),
+                    });
+                }
+                // Editing ends when the text field loses the keyboard, e.g.
+                // after pressing enter or clicking elsewhere.
+                if title_response.double_clicked() {
+                    text_response.request_focus();
+                    ui.memory().data.insert_temp(editing_title_id, frame_id);
+                } else if !text_response.has_focus() {
+                    ui.memory().data.remove::<FrameId>(editing_title_id);
+                }
+            }
+
+            let frame = &self.frames[frame_id];
+            let mut shapes = vec![
+                Shape::Rect(RectShape {
+                    rect: frame_rect,
+                    rounding,
+                    fill: frame.color.linear_multiply(0.25),
+                    stroke,
+                }),
+                Shape::Rect(RectShape {
+                    rect: title_rect,
+                    rounding,
+                    fill: frame.color,
+                    stroke: Stroke::none(),
+                }),
+                Shape::convex_polygon(
+                    vec![
+                        frame_rect.max,
+                        frame_rect.max - vec2(0.0, handle_size.y),
+                        frame_rect.max - vec2(handle_size.x, 0.0),
+                    ],
+                    frame.color,
+                    Stroke::none(),
+                ),
+            ];
+            if !editing {
+                shapes.push(Shape::text(
+                    &ui.fonts(),
+                    title_pos,
+                    Align2::LEFT_CENTER,
+                    &frame.title,
+                    font,
+                    style.frame_title_text,
+                ));
+            }
+            ui.painter().set(frame_shape, Shape::Vec(shapes));
+        }
 
         let r = ui.allocate_rect(ui.min_rect(), Sense::click().union(Sense::drag()));
         if r.clicked() {
@@ -173,6 +675,7 @@ where
         }
 
         /* Draw the node finder, if open */
+        let snap_spacing = self.snap_spacing();
         let mut should_close_node_finder = false;
         if let Some(ref mut node_finder) = self.node_finder {
             let mut node_finder_area = Area::new("node_finder").order(Order::Foreground);
@@ -180,17 +683,31 @@ where
                 node_finder_area = node_finder_area.current_pos(pos);
             }
             node_finder_area.show(ui.ctx(), |ui| {
-                if let Some(node_kind) = node_finder.show(ui, all_kinds, user_state) {
+                if let Some(node_kind) = node_finder.show(ui, all_kinds, user_state, &style) {
                     let new_node = self.graph.add_node(
                         node_kind.node_graph_label(user_state),
                         node_kind.user_data(user_state),
                         |graph, node_id| node_kind.build_node(graph, user_state, node_id),
                     );
-                    self.node_positions.insert(
-                        new_node,
-                        cursor_pos - self.pan_zoom.pan - editor_rect.min.to_vec2(),
-                    );
+                    let mut position = self.pan_zoom.screen_to_graph(editor_rect.min, cursor_pos);
+                    if let Some(spacing) = snap_spacing {
+                        position = grid_point(position, spacing);
+                    }
+                    self.node_positions.insert(new_node, position);
                     self.node_order.push(new_node);
+                    self.history.seal();
+                    self.history.push(EditorCommand::ChangeNodes {
+                        created: NodeRecord::capture(
+                            &self.graph,
+                            &self.node_positions,
+                            &self.node_order,
+                            &self.reroute_knots,
+                            new_node,
+                        )
+                        .into_iter()
+                        .collect(),
+                        deleted: vec![],
+                    });
 
                     should_close_node_finder = true;
                     delayed_responses.push(NodeResponse::CreatedNode(new_node));
@@ -209,10 +726,11 @@ where
         }
 
         /* Draw connections */
-        if let Some((_, ref locator)) = self.connection_in_progress {
+        if let Some((origin_node, ref locator)) = self.connection_in_progress {
             let port_type = self.graph.any_param_type(*locator).unwrap();
-            let connection_color = port_type.data_type_color(user_state);
-            let start_pos = port_locations[locator];
+            let start_pos =
+                new_connection_pos(&self.graph, &port_locations, *locator, self.pan_zoom.zoom)
+                    .expect("Port of ongoing connection should have been drawn");
 
             // Find a port to connect to
             fn snap_to_ports<
@@ -228,112 +746,202 @@ where
                 ports: &SlotMap<Key, Value>,
                 port_locations: &PortLocations,
                 cursor_pos: Pos2,
-            ) -> Pos2 {
-                ports
-                    .iter()
-                    .find_map(|(port_id, _)| {
-                        let compatible_ports = graph
-                            .any_param_type(port_id.into())
-                            .map(|other| other == port_type)
-                            .unwrap_or(false);
-
-                        if compatible_ports {
-                            port_locations.get(&port_id.into()).and_then(|port_pos| {
-                                if port_pos.distance(cursor_pos) < DISTANCE_TO_CONNECT {
-                                    Some(*port_pos)
+                zoom: f32,
+            ) -> Option<(AnyParameterId, Pos2)> {
+                ports.iter().find_map(|(port_id, _)| {
+                    let compatible_ports = graph
+                        .any_param_type(port_id.into())
+                        .map(|other| other == port_type)
+                        .unwrap_or(false);
+
+                    if compatible_ports {
+                        new_connection_pos(graph, port_locations, port_id.into(), zoom).and_then(
+                            |port_pos| {
+                                if port_pos.distance(cursor_pos) < DISTANCE_TO_CONNECT * zoom {
+                                    Some((port_id.into(), port_pos))
                                 } else {
                                     None
                                 }
-                            })
-                        } else {
-                            None
-                        }
-                    })
-                    .unwrap_or(cursor_pos)
+                            },
+                        )
+                    } else {
+                        None
+                    }
+                })
             }
 
-            let (src_pos, dst_pos) = match locator {
-                AnyParameterId::Output(_) => (
-                    start_pos,
-                    snap_to_ports(
-                        &self.graph,
-                        port_type,
-                        &self.graph.inputs,
-                        &port_locations,
-                        cursor_pos,
-                    ),
+            let snapped = match locator {
+                AnyParameterId::Output(_) => snap_to_ports(
+                    &self.graph,
+                    port_type,
+                    &self.graph.inputs,
+                    &port_locations,
+                    cursor_pos,
+                    self.pan_zoom.zoom,
                 ),
-                AnyParameterId::Input(_) => (
-                    snap_to_ports(
-                        &self.graph,
-                        port_type,
-                        &self.graph.outputs,
-                        &port_locations,
-                        cursor_pos,
-                    ),
-                    start_pos,
+                AnyParameterId::Input(_) => snap_to_ports(
+                    &self.graph,
+                    port_type,
+                    &self.graph.outputs,
+                    &port_locations,
+                    cursor_pos,
+                    self.pan_zoom.zoom,
                 ),
             };
-            draw_connection(ui.painter(), src_pos, dst_pos, connection_color);
+            let end_pos = snapped.map(|(_, pos)| pos).unwrap_or(cursor_pos);
+            let (src_pos, dst_pos) = match locator {
+                AnyParameterId::Output(_) => (start_pos, end_pos),
+                AnyParameterId::Input(_) => (end_pos, start_pos),
+            };
+
+            // Show the user that the connection would be refused on release
+            let rejected = snapped
+                .map(|(port, _)| {
+                    connection_rejected(
+                        &self.graph,
+                        origin_node,
+                        *locator,
+                        port,
+                        self.reject_cycles,
+                    )
+                })
+                .unwrap_or(false);
+            let connection_color = if rejected {
+                style.wire_rejected
+            } else {
+                port_type.data_type_color(user_state)
+            };
+
+            draw_connection(
+                ui.painter(),
+                src_pos,
+                dst_pos,
+                connection_color,
+                &style,
+                self.pan_zoom.zoom,
+            );
         }
 
-        for (input, output) in self.graph.iter_connections() {
+        let hovered_connection = if self.connection_in_progress.is_none()
+            && self.ongoing_box_selection.is_none()
+            && self.ongoing_knife_cut.is_none()
+            && cursor_in_editor
+            && !cursor_in_finder
+            && !cursor_in_minimap
+            && !cursor_over_node
+            && !port_locations.values().any(|port_pos| {
+                port_pos.distance(cursor_pos) < DISTANCE_TO_CONNECT * self.pan_zoom.zoom
+            }) {
+            wires
+                .iter()
+                .map(|wire| (wire.connection, wire.distance(cursor_pos).0))
+                .filter(|(_, distance)| *distance < DISTANCE_TO_CONNECT * self.pan_zoom.zoom)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(connection, _)| connection)
+        } else {
+            None
+        };
+
+        for wire in &wires {
+            if self.selected_connections.contains(&wire.connection) {
+                for polyline in &wire.polylines {
+                    ui.painter().add(Shape::line(
+                        polyline.clone(),
+                        Stroke::new(
+                            (style.wire_width + 4.0) * self.pan_zoom.zoom,
+                            style.wire_selected_outline,
+                        ),
+                    ));
+                }
+            }
+        }
+        for wire in &wires {
+            let (_, output) = wire.connection;
             let port_type = self
                 .graph
                 .any_param_type(AnyParameterId::Output(output))
                 .unwrap();
-            let connection_color = port_type.data_type_color(user_state);
-            let src_pos = port_locations[&AnyParameterId::Output(output)];
-            let dst_pos = port_locations[&AnyParameterId::Input(input)];
-            draw_connection(ui.painter(), src_pos, dst_pos, connection_color);
+            let mut connection_color = port_type.data_type_color(user_state);
+            if hovered_connection == Some(wire.connection) {
+                connection_color = connection_color.lighten(style.wire_hovered_tint);
+            }
+            for (start, end) in wire.stretches() {
+                draw_connection(
+                    ui.painter(),
+                    start,
+                    end,
+                    connection_color,
+                    &style,
+                    self.pan_zoom.zoom,
+                );
+            }
+            for knot in &wire.knots {
+                ui.painter().circle_filled(
+                    *knot,
+                    KNOT_RADIUS * self.pan_zoom.zoom,
+                    connection_color,
+                );
+            }
         }
 
-        /* Handle responses from drawing nodes */
+        for (input, before) in edited_values {
+            self.history.push(EditorCommand::SetValue {
+                input,
+                before,
+                after: self.graph[input].value.clone(),
+            });
+        }
 
-        // Some responses generate additional responses when processed. These
-        // are stored here to report them back to the user.
-        let mut extra_responses: Vec<NodeResponse<UserResponse, NodeData>> = Vec::new();
+        /* Handle responses from drawing nodes */
 
-        for response in delayed_responses.iter() {
+        for response in delayed_responses.iter_mut() {
             match response {
                 NodeResponse::ConnectEventStarted(node_id, port) => {
                     self.connection_in_progress = Some((*node_id, *port));
                 }
                 NodeResponse::ConnectEventEnded { input, output } => {
-                    self.graph.add_connection(*output, *input)
+                    let before = self.graph.connections(*input).to_vec();
+                    self.graph.add_connection(*output, *input);
+                    self.record_connections(*input, before);
                 }
-                NodeResponse::CreatedNode(_) => {
+                NodeResponse::CreatedNode(_) | NodeResponse::ValueChanged { .. } => {
                     //Convenience NodeResponse for users
                 }
                 NodeResponse::SelectNode(node_id) => {
                     self.selected_nodes = Vec::from([*node_id]);
+                    self.selected_connections.clear();
+                    self.selected_frames.clear();
                 }
                 NodeResponse::DeleteNodeUi(node_id) => {
-                    let (node, disc_events) = self.graph.remove_node(*node_id);
-                    // Pass the disconnection responses first so user code can perform cleanup
-                    // before node removal response.
-                    extra_responses.extend(
-                        disc_events
-                            .into_iter()
-                            .map(|(input, output)| NodeResponse::DisconnectEvent { input, output }),
-                    );
-                    // Pass the full node as a response so library users can
-                    // listen for it and get their user data.
-                    extra_responses.push(NodeResponse::DeleteNodeFull {
-                        node_id: *node_id,
-                        node,
-                    });
-                    self.node_positions.remove(*node_id);
-                    // Make sure to not leave references to old nodes hanging
-                    self.selected_nodes.retain(|id| *id != *node_id);
-                    self.node_order.retain(|id| *id != *node_id);
+                    extra_responses.extend(self.delete_nodes(&[*node_id]));
                 }
                 NodeResponse::DisconnectEvent { input, output } => {
                     let other_node = self.graph.get_output(*output).node;
-                    self.graph.remove_connection(*input);
+                    let before = self.graph.connections(*input).to_vec();
+                    self.graph.remove_connection(*input, *output);
+                    self.record_connections(*input, before);
                     self.connection_in_progress =
                         Some((other_node, AnyParameterId::Output(*output)));
                 }
+                NodeResponse::CollapseNode { node, collapsed } => {
+                    if *collapsed {
+                        self.collapsed_nodes.insert(*node, ());
+                    } else {
+                        self.collapsed_nodes.remove(*node);
+                    }
+                    self.history.push(EditorCommand::SetCollapsed {
+                        node: *node,
+                        collapsed: *collapsed,
+                    });
+                }
+                NodeResponse::ResizeNode { node, size } => {
+                    let before = self.node_sizes.insert(*node, *size);
+                    self.history.push(EditorCommand::ResizeNode {
+                        node: *node,
+                        before,
+                        after: Some(*size),
+                    });
+                }
                 NodeResponse::RaiseNode(node_id) => {
                     let old_pos = self
                         .node_order
@@ -344,15 +952,11 @@ where
                     self.node_order.push(*node_id);
                 }
                 NodeResponse::MoveNode { node, drag_delta } => {
-                    self.node_positions[*node] += *drag_delta;
-                    // Handle multi-node selection movement
-                    if self.selected_nodes.contains(node) && self.selected_nodes.len() > 1 {
-                        for n in self.selected_nodes.iter().copied() {
-                            if n != *node {
-                                self.node_positions[n] += *drag_delta;
-                            }
-                        }
-                    }
+                    let remainders_id = ui.id().with("snap_remainders");
+                    let mut remainders: HashMap<NodeId, Vec2> =
+                        ui.memory().data.get_temp(remainders_id).unwrap_or_default();
+                    *drag_delta = self.drag_nodes(*node, *drag_delta, &mut remainders);
+                    ui.memory().data.insert_temp(remainders_id, remainders);
                 }
                 NodeResponse::User(_) => {
                     // These are handled by the user code.
@@ -365,14 +969,15 @@ where
 
         // Handle box selection
         if let Some(box_start) = self.ongoing_box_selection {
-            let selection_rect = Rect::from_two_pos(cursor_pos, box_start);
-            let bg_color = Color32::from_rgba_unmultiplied(200, 200, 200, 20);
-            let stroke_color = Color32::from_rgba_unmultiplied(200, 200, 200, 180);
+            let selection_rect = Rect::from_two_pos(
+                cursor_pos,
+                self.pan_zoom.graph_to_screen(editor_rect.min, box_start),
+            );
             ui.painter().rect(
                 selection_rect,
                 2.0,
-                bg_color,
-                Stroke::new(3.0, stroke_color),
+                style.box_selection_fill,
+                Stroke::new(3.0, style.box_selection_stroke),
             );
 
             self.selected_nodes = node_rects
@@ -385,6 +990,97 @@ where
                     }
                 })
                 .collect();
+            self.selected_connections = wires
+                .iter()
+                .filter(|wire| {
+                    wire.polylines
+                        .iter()
+                        .flatten()
+                        .any(|point| selection_rect.contains(*point))
+                })
+                .map(|wire| wire.connection)
+                .collect();
+            self.selected_frames = self
+                .frames
+                .iter()
+                .filter(|(_, frame)| selection_rect.contains_rect(to_screen(frame.rect)))
+                .map(|(frame_id, _)| frame_id)
+                .collect();
+        }
+
+        // Handle knife cuts. Every connection crossed by the knife is removed
+        // when the mouse is released.
+        let mut knife_released = false;
+        if let Some(knife_start) = self.ongoing_knife_cut {
+            let knife_start = self.pan_zoom.graph_to_screen(editor_rect.min, knife_start);
+            ui.painter().extend(Shape::dashed_line(
+                &[knife_start, cursor_pos],
+                Stroke::new(2.0, style.knife),
+                6.0,
+                4.0,
+            ));
+
+            if ui.ctx().input().pointer.any_released() {
+                let cut: Vec<(InputId, OutputId)> = wires
+                    .iter()
+                    .filter(|wire| {
+                        wire.stretches().any(|(start, end)| {
+                            curves::segment_intersects_bezier(
+                                knife_start,
+                                cursor_pos,
+                                curves::connection_points(start, end, self.pan_zoom.zoom),
+                            )
+                        })
+                    })
+                    .map(|wire| wire.connection)
+                    .collect();
+                extra_responses.extend(self.delete_connections(&cut));
+                self.ongoing_knife_cut = None;
+                knife_released = true;
+            }
+        }
+
+        if ui.ctx().input().pointer.any_released() {
+            ui.memory()
+                .data
+                .remove::<HashMap<NodeId, Vec2>>(ui.id().with("snap_remainders"));
+        }
+
+        /* Draw the minimap */
+        if let Some(minimap_response) = minimap_response {
+            let viewport = |pan_zoom: PanZoom| {
+                Rect::from_min_max(
+                    pan_zoom.screen_to_graph(editor_rect.min, editor_rect.min),
+                    pan_zoom.screen_to_graph(editor_rect.min, editor_rect.max),
+                )
+            };
+            let transform = MinimapTransform::new(
+                minimap_rect,
+                minimap::bounds(self.node_rects.values().copied(), viewport(self.pan_zoom)),
+            );
+            // Center the viewport on the point under the cursor
+            if let Some(pointer) = minimap_response.interact_pointer_pos() {
+                let target = transform.minimap_to_graph(pointer);
+                self.pan_zoom.pan =
+                    editor_rect.size() / 2.0 - target.to_vec2() * self.pan_zoom.zoom;
+            }
+
+            let painter = ui.painter_at(minimap_rect);
+            painter.rect_filled(minimap_rect, 4.0, style.minimap_background);
+            for node_id in self.node_order.iter().copied() {
+                if let Some(rect) = self.node_rects.get(&node_id) {
+                    let color = self.graph[node_id]
+                        .user_data
+                        .titlebar_color(ui, node_id, &self.graph, user_state)
+                        .unwrap_or_else(|| style.node_background.lighten(style.titlebar_tint));
+                    painter.rect_filled(transform.rect_to_minimap(*rect), 1.0, color);
+                }
+            }
+            painter.rect_stroke(
+                transform.rect_to_minimap(viewport(self.pan_zoom)),
+                0.0,
+                Stroke::new(1.5, style.minimap_viewport),
+            );
         }
 
         // Push any responses that were generated during response handling.
@@ -400,8 +1096,18 @@ where
         if mouse.any_released() && self.connection_in_progress.is_some() {
             self.connection_in_progress = None;
         }
+        // A gesture ends when the mouse is released. Everything it did is
+        // undone at once.
+        if mouse.any_released() {
+            self.history.seal();
+        }
 
-        if mouse.secondary_released() && cursor_in_editor && !cursor_in_finder {
+        if mouse.secondary_released()
+            && cursor_in_editor
+            && !cursor_in_finder
+            && !cursor_in_minimap
+            && !knife_released
+        {
             self.node_finder = Some(NodeFinder::new_at(cursor_pos));
         }
         if ui.ctx().input().key_pressed(Key::Escape) {
@@ -412,15 +1118,69 @@ where
             self.pan_zoom.pan += ui.ctx().input().pointer.delta();
         }
 
+        // Zoom around the cursor, using a pinch gesture or Ctrl and the mouse
+        // wheel. The wheel alone only zooms over the background, so that it
+        // still scrolls the widgets inside the nodes.
+        if cursor_in_editor && !cursor_in_finder && !cursor_in_minimap {
+            let mut zoom_factor = ui.ctx().input().zoom_delta();
+            if !cursor_over_node {
+                zoom_factor *= (ui.ctx().input().scroll_delta.y * SCROLL_ZOOM_SPEED).exp();
+            }
+            if zoom_factor != 1.0 {
+                self.pan_zoom.adjust_zoom(
+                    self.pan_zoom.zoom * (zoom_factor - 1.0),
+                    cursor_pos - editor_rect.min,
+                    self.zoom_min,
+                    self.zoom_max,
+                );
+            }
+        }
+
         // Deselect and deactivate finder if the editor backround is clicked,
-        // *or* if the the mouse clicks off the ui
+        // *or* if the the mouse clicks off the ui. Clicking a connection
+        // selects it instead.
         if click_on_background || (mouse.any_click() && !cursor_in_editor) {
             self.selected_nodes = Vec::new();
+            self.selected_connections = Vec::new();
+            self.selected_frames = Vec::new();
             self.node_finder = None;
+            if click_on_background {
+                self.selected_connections.extend(hovered_connection);
+            }
+        }
+
+        // Double-clicking a connection adds a reroute knot under the cursor
+        if r.double_clicked() {
+            if let Some(wire) = wires
+                .iter()
+                .find(|wire| Some(wire.connection) == hovered_connection)
+            {
+                let (input, output) = wire.connection;
+                let (_, stretch) = wire.distance(cursor_pos);
+                let before = self.reroute_knots.get(input, output).to_vec();
+                self.reroute_knots.insert(
+                    input,
+                    output,
+                    stretch,
+                    self.pan_zoom.screen_to_graph(editor_rect.min, cursor_pos),
+                );
+                self.history.push(EditorCommand::SetKnots {
+                    input,
+                    output,
+                    before,
+                    after: self.reroute_knots.get(input, output).to_vec(),
+                });
+            }
         }
 
         if drag_started_on_background && mouse.primary_down() {
-            self.ongoing_box_selection = Some(cursor_pos);
+            self.ongoing_box_selection =
+                Some(self.pan_zoom.screen_to_graph(editor_rect.min, cursor_pos));
+        }
+        // Like in Blender, the knife is a right drag while holding Ctrl
+        if drag_started_on_background && mouse.secondary_down() && ui.ctx().input().modifiers.ctrl {
+            self.ongoing_knife_cut =
+                Some(self.pan_zoom.screen_to_graph(editor_rect.min, cursor_pos));
         }
         if mouse.primary_released() || drag_released_on_background {
             self.ongoing_box_selection = None;
@@ -432,17 +1192,261 @@ where
             cursor_in_finder,
         }
     }
+
+    /// Records the change of the connections of `input`, if any.
+    fn record_connections(&mut self, input: InputId, before: Vec<OutputId>) {
+        if before != self.graph.connections(input) {
+            self.history.push(EditorCommand::set_connections(
+                &self.graph,
+                &self.reroute_knots,
+                input,
+                before,
+            ));
+        }
+    }
+}
+
+impl<NodeData, DataType, ValueType, NodeTemplate, UserState>
+    GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
+{
+    /// Returns the spacing of the grid when nodes snap to it.
+    fn snap_spacing(&self) -> Option<f32> {
+        if self.snap_to_grid && self.grid_spacing > 0.0 {
+            Some(self.grid_spacing)
+        } else {
+            None
+        }
+    }
+
+    /// Moves `node` by `drag_delta`, along with the rest of the selection when
+    /// `node` is part of it, and records the move. Returns the delta `node`
+    /// was actually moved by.
+    ///
+    /// When snapping, each node jumps from one grid point to the next. The
+    /// part of the drag not applied yet to a node is kept in `remainders` for
+    /// the next frames, so that slow drags still move it.
+    fn drag_nodes(
+        &mut self,
+        node: NodeId,
+        drag_delta: Vec2,
+        remainders: &mut HashMap<NodeId, Vec2>,
+    ) -> Vec2 {
+        let mut nodes = vec![node];
+        if self.selected_nodes.contains(&node) {
+            nodes.extend(self.selected_nodes.iter().copied().filter(|n| *n != node));
+        }
+        let mut moves = Vec::with_capacity(nodes.len());
+        for n in nodes {
+            let position = self.node_positions[n];
+            let delta = match self.snap_spacing() {
+                Some(spacing) => {
+                    let unsnapped =
+                        position + remainders.get(&n).copied().unwrap_or_default() + drag_delta;
+                    let snapped = grid_point(unsnapped, spacing);
+                    remainders.insert(n, unsnapped - snapped);
+                    snapped - position
+                }
+                None => drag_delta,
+            };
+            self.node_positions[n] += delta;
+            moves.push((n, delta));
+        }
+        let node_delta = moves[0].1;
+        self.history.push(EditorCommand::MoveNodes { moves });
+        node_delta
+    }
+}
+
+/// A connection as drawn on screen: a curve from the output to the input,
+/// going through each of its reroute knots.
+struct DrawnConnection {
+    connection: (InputId, OutputId),
+    src_pos: Pos2,
+    knots: Vec<Pos2>,
+    dst_pos: Pos2,
+    /// The approximation of each curve between two consecutive points by line
+    /// segments.
+    polylines: Vec<Vec<Pos2>>,
+}
+
+impl DrawnConnection {
+    fn new(
+        connection: (InputId, OutputId),
+        src_pos: Pos2,
+        knots: Vec<Pos2>,
+        dst_pos: Pos2,
+        zoom: f32,
+    ) -> Self {
+        let mut wire = Self {
+            connection,
+            src_pos,
+            knots,
+            dst_pos,
+            polylines: vec![],
+        };
+        wire.polylines = wire
+            .stretches()
+            .map(|(start, end)| curves::flatten(curves::connection_points(start, end, zoom)))
+            .collect();
+        wire
+    }
+
+    /// Returns the start and end points of each curve of the connection.
+    fn stretches(&self) -> impl Iterator<Item = (Pos2, Pos2)> + '_ {
+        let points = std::iter::once(self.src_pos)
+            .chain(self.knots.iter().copied())
+            .chain(std::iter::once(self.dst_pos));
+        points.clone().zip(points.skip(1))
+    }
+
+    /// Returns the distance from `pos` to the connection, and the index of
+    /// the closest curve.
+    fn distance(&self, pos: Pos2) -> (f32, usize) {
+        self.polylines
+            .iter()
+            .map(|polyline| curves::distance_to_polyline(polyline, pos))
+            .enumerate()
+            .map(|(index, distance)| (distance, index))
+            .fold((f32::INFINITY, 0), |closest, other| {
+                if other.0 < closest.0 {
+                    other
+                } else {
+                    closest
+                }
+            })
+    }
+}
+
+/// Returns the number of slots drawn on the port of `input`: one for each of
+/// its connections, plus a free one when the input accepts more.
+fn input_slot_count<NodeData, DataType, ValueType>(
+    graph: &Graph<NodeData, DataType, ValueType>,
+    input: InputId,
+) -> usize {
+    let connected = graph.connections(input).len();
+    let full = graph[input]
+        .max_connections
+        .map(|max| connected >= max.get() as usize)
+        .unwrap_or(false);
+    if full {
+        connected
+    } else {
+        connected + 1
+    }
+}
+
+/// Returns the center of slot number `slot` on a port with `slot_count` slots,
+/// centered at `port_pos`.
+fn slot_pos(port_pos: Pos2, slot: usize, slot_count: usize, zoom: f32) -> Pos2 {
+    let offset = slot as f32 - (slot_count as f32 - 1.0) / 2.0;
+    port_pos + vec2(0.0, offset * PORT_SLOT_SPACING * zoom)
+}
+
+/// Returns the position where a new connection to `param` gets attached. For
+/// inputs, this is the last slot of the port: either the free one, or the one
+/// replaced when the input is full.
+fn new_connection_pos<NodeData, DataType, ValueType>(
+    graph: &Graph<NodeData, DataType, ValueType>,
+    port_locations: &PortLocations,
+    param: AnyParameterId,
+    zoom: f32,
+) -> Option<Pos2> {
+    let port_pos = *port_locations.get(&param)?;
+    Some(match param {
+        AnyParameterId::Input(input) => {
+            let slot_count = input_slot_count(graph, input);
+            slot_pos(port_pos, slot_count - 1, slot_count, zoom)
+        }
+        AnyParameterId::Output(_) => port_pos,
+    })
+}
+
+/// Returns true when a connection between `origin_param`, on `origin_node`,
+/// and `other_param` should be refused. Self-loops are always refused, and
+/// other cycles are refused when `reject_cycles` is set.
+fn connection_rejected<NodeData, DataType, ValueType>(
+    graph: &Graph<NodeData, DataType, ValueType>,
+    origin_node: NodeId,
+    origin_param: AnyParameterId,
+    other_param: AnyParameterId,
+    reject_cycles: bool,
+) -> bool {
+    match (origin_param, other_param) {
+        (AnyParameterId::Input(input), AnyParameterId::Output(output))
+        | (AnyParameterId::Output(output), AnyParameterId::Input(input)) => {
+            let other_node = match other_param {
+                AnyParameterId::Input(input) => graph[input].node,
+                AnyParameterId::Output(output) => graph[output].node,
+            };
+            origin_node == other_node || (reject_cycles && graph.would_create_cycle(output, input))
+        }
+        _ => true,
+    }
 }
 
-fn draw_connection(painter: &Painter, src_pos: Pos2, dst_pos: Pos2, color: Color32) {
-    let connection_stroke = egui::Stroke { width: 5.0, color };
+/// Returns the grid point closest to `pos`.
+fn grid_point(pos: Pos2, spacing: f32) -> Pos2 {
+    ((pos.to_vec2() / spacing).round() * spacing).to_pos2()
+}
 
-    let control_scale = ((dst_pos.x - src_pos.x) / 2.0).max(30.0);
-    let src_control = src_pos + Vec2::X * control_scale;
-    let dst_control = dst_pos - Vec2::X * control_scale;
+/// Draws the lines of a grid with the given `spacing`, in graph units, across
+/// the editor.
+fn draw_grid(
+    painter: &Painter,
+    editor_rect: Rect,
+    pan_zoom: PanZoom,
+    spacing: f32,
+    color: Color32,
+) {
+    let mut spacing = spacing;
+    while spacing * pan_zoom.zoom < MIN_GRID_SPACING {
+        spacing *= 2.0;
+    }
+    let min = pan_zoom.screen_to_graph(editor_rect.min, editor_rect.min);
+    let max = pan_zoom.screen_to_graph(editor_rect.min, editor_rect.max);
+    let stroke = Stroke::new(1.0, color);
+
+    let mut x = (min.x / spacing).ceil() * spacing;
+    while x <= max.x {
+        let screen_x = pan_zoom.graph_to_screen(editor_rect.min, pos2(x, 0.0)).x;
+        painter.line_segment(
+            [
+                pos2(screen_x, editor_rect.top()),
+                pos2(screen_x, editor_rect.bottom()),
+            ],
+            stroke,
+        );
+        x += spacing;
+    }
+    let mut y = (min.y / spacing).ceil() * spacing;
+    while y <= max.y {
+        let screen_y = pan_zoom.graph_to_screen(editor_rect.min, pos2(0.0, y)).y;
+        painter.line_segment(
+            [
+                pos2(editor_rect.left(), screen_y),
+                pos2(editor_rect.right(), screen_y),
+            ],
+            stroke,
+        );
+        y += spacing;
+    }
+}
+
+fn draw_connection(
+    painter: &Painter,
+    src_pos: Pos2,
+    dst_pos: Pos2,
+    color: Color32,
+    style: &NodeGraphStyle,
+    zoom: f32,
+) {
+    let connection_stroke = egui::Stroke {
+        width: style.wire_width * zoom,
+        color,
+    };
 
     let bezier = CubicBezierShape::from_points_stroke(
-        [src_pos, src_control, dst_control, dst_pos],
+        curves::connection_points(src_pos, dst_pos, zoom),
         false,
         Color32::TRANSPARENT,
         connection_stroke,
@@ -461,10 +1465,14 @@ where
         ValueType = ValueType,
     >,
     UserResponse: UserResponseTrait,
-    ValueType:
-        WidgetValueTrait<Response = UserResponse, UserState = UserState, NodeData = NodeData>,
+    ValueType: WidgetValueTrait<Response = UserResponse, UserState = UserState, NodeData = NodeData>
+        + Clone
+        + PartialEq,
     DataType: DataTypeTrait<UserState>,
 {
+    #[deprecated(
+        note = "Nodes are sized by `NodeDataTrait::size_constraints`. This is the default size of `NodeSizeConstraints::default()`."
+    )]
     pub const MAX_NODE_SIZE: [f32; 2] = [200.0, 200.0];
 
     pub fn show(
@@ -472,11 +1480,20 @@ where
         ui: &mut Ui,
         user_state: &mut UserState,
     ) -> Vec<NodeResponse<UserResponse, NodeData>> {
+        let constraints = self.graph[self.node_id].user_data.size_constraints();
+        let size = self
+            .size
+            .map(|size| constraints.clamp(size))
+            .unwrap_or(constraints.default);
         let mut child_ui = ui.child_ui_with_id_source(
-            Rect::from_min_size(*self.position + self.pan, Self::MAX_NODE_SIZE.into()),
+            Rect::from_min_size(
+                (self.position.to_vec2() * self.zoom + self.pan).to_pos2(),
+                size * self.zoom,
+            ),
             Layout::default(),
             self.node_id,
         );
+        child_ui.set_style(ui.style().scaled(self.zoom));
 
         Self::show_graph_node(self, &mut child_ui, user_state)
     }
@@ -488,20 +1505,15 @@ where
         ui: &mut Ui,
         user_state: &mut UserState,
     ) -> Vec<NodeResponse<UserResponse, NodeData>> {
-        let margin = egui::vec2(15.0, 5.0);
+        let zoom = self.zoom;
+        let margin = egui::vec2(15.0, 5.0) * zoom;
         let mut responses = Vec::<NodeResponse<UserResponse, NodeData>>::new();
 
-        let background_color;
-        let text_color;
-        if ui.visuals().dark_mode {
-            background_color = color_from_hex("#3f3f3f").unwrap();
-            text_color = color_from_hex("#fefefe").unwrap();
-        } else {
-            background_color = color_from_hex("#ffffff").unwrap();
-            text_color = color_from_hex("#505050").unwrap();
-        }
+        let style = self.style;
+        let background_color = style.node_background;
+        let text_color = style.node_text;
 
-        ui.visuals_mut().widgets.noninteractive.fg_stroke = Stroke::new(2.0, text_color);
+        ui.visuals_mut().widgets.noninteractive.fg_stroke = Stroke::new(2.0 * zoom, text_color);
 
         // Preallocate shapes to paint below contents
         let outline_shape = ui.painter().add(Shape::Noop);
@@ -527,10 +1539,17 @@ where
                         .text_style(TextStyle::Button)
                         .color(text_color),
                 ));
-                ui.add_space(8.0); // The size of the little cross icon
+                ui.add_space(8.0 * zoom); // The size of the little cross icon
+                ui.add_space(COLLAPSE_TOGGLE_SIZE * zoom);
+                if self.evaluation_state.is_some() {
+                    ui.add_space(EVALUATION_STATE_SIZE * zoom);
+                }
             });
             ui.add_space(margin.y);
             title_height = ui.min_size().y;
+            if self.collapsed {
+                return;
+            }
 
             // First pass: Draw the inner fields. Compute port heights
             let inputs = self.graph[self.node_id].inputs.clone();
@@ -548,6 +1567,7 @@ where
                         // dummy value. This requires `ValueType` to implement
                         // Default, but results in a totally safe alternative.
                         let mut value = std::mem::take(&mut self.graph[param_id].value);
+                        let previous_value = value.clone();
                         let node_responses = value.value_widget(
                             &param_name,
                             self.node_id,
@@ -555,9 +1575,24 @@ where
                             user_state,
                             &self.graph[self.node_id].user_data,
                         );
+                        let changed = value != previous_value;
                         self.graph[param_id].value = value;
+                        if changed {
+                            self.edited_values.push((param_id, previous_value));
+                            responses.push(NodeResponse::ValueChanged {
+                                node: self.node_id,
+                                input: param_id,
+                            });
+                        }
                         responses.extend(node_responses.into_iter().map(NodeResponse::User));
                     }
+                    // Leave enough room for the slots of wide inputs
+                    let slots_height =
+                        input_slot_count(self.graph, param_id) as f32 * PORT_SLOT_SPACING * zoom;
+                    let row_height = ui.min_rect().bottom() - height_before;
+                    if row_height < slots_height {
+                        ui.add_space(slots_height - row_height);
+                    }
                     let height_after = ui.min_rect().bottom();
                     input_port_heights.push((height_before + height_after) / 2.0);
                 }
@@ -571,17 +1606,26 @@ where
                 output_port_heights.push((height_before + height_after) / 2.0);
             }
 
-            responses.extend(
-                self.graph[self.node_id]
-                    .user_data
-                    .bottom_ui(ui, self.node_id, self.graph, user_state)
-                    .into_iter(),
-            );
+            responses.extend(self.graph[self.node_id].user_data.bottom_ui(
+                ui,
+                self.node_id,
+                self.graph,
+                user_state,
+            ));
         });
 
         // Second pass, iterate again to draw the ports. This happens outside
         // the child_ui because we want ports to overflow the node background.
 
+        // Nodes resized by the user take all of their size, or more if their
+        // contents don't fit.
+        if self.size.is_some() {
+            if self.collapsed {
+                child_ui.expand_to_include_x(inner_rect.right());
+            } else {
+                child_ui.expand_to_include_rect(inner_rect);
+            }
+        }
         let outer_rect = child_ui.min_rect().expand2(margin);
         let port_left = outer_rect.left();
         let port_right = outer_rect.right();
@@ -597,15 +1641,43 @@ where
             param_id: AnyParameterId,
             port_locations: &mut PortLocations,
             ongoing_drag: Option<(NodeId, AnyParameterId)>,
-            is_connected_input: bool,
+            reject_cycles: bool,
+            style: &NodeGraphStyle,
+            zoom: f32,
         ) where
             DataType: DataTypeTrait<UserState>,
             UserResponse: UserResponseTrait,
             NodeData: NodeDataTrait,
         {
             let port_type = graph.any_param_type(param_id).unwrap();
+            let port_color = port_type.data_type_color(user_state);
+
+            // Each connection of an input gets its own slot, plus a free slot
+            // when the input accepts more. Outputs always have a single slot.
+            let slots: SVec<Option<OutputId>> = match param_id {
+                AnyParameterId::Input(input) => {
+                    let connections = graph.connections(input);
+                    let mut slots: SVec<_> = connections.iter().copied().map(Some).collect();
+                    if slots.len() < input_slot_count(graph, input) {
+                        slots.push(None);
+                    }
+                    slots
+                }
+                AnyParameterId::Output(_) => SVec::from_elem(None, 1),
+            };
 
-            let port_rect = Rect::from_center_size(port_pos, egui::vec2(10.0, 10.0));
+            let slot_positions: SVec<Pos2> = (0..slots.len())
+                .map(|slot| slot_pos(port_pos, slot, slots.len(), zoom))
+                .collect();
+            if slots.len() > 1 {
+                let first = slot_positions[0];
+                let last = slot_positions[slots.len() - 1];
+                ui.painter().rect_filled(
+                    Rect::from_two_pos(first, last).expand(style.port_radius / 2.0 * zoom),
+                    style.port_radius * zoom,
+                    port_color.linear_multiply(0.4),
+                );
+            }
 
             let sense = if ongoing_drag.is_some() {
                 Sense::hover()
@@ -613,118 +1685,177 @@ where
                 Sense::click_and_drag()
             };
 
-            let resp = ui.allocate_rect(port_rect, sense);
+            let pointer_pos = ui.ctx().pointer_hover_pos();
+            for (connection, slot_center) in slots.iter().zip(slot_positions.iter().copied()) {
+                let slot_rect = Rect::from_center_size(
+                    slot_center,
+                    Vec2::splat(2.0 * style.port_radius * zoom),
+                );
+                let resp = ui.allocate_rect(slot_rect, sense);
 
-            // Check if the distance between the port and the mouse is the distance to connect
-            let close_enough = if let Some(pointer_pos) = ui.ctx().pointer_hover_pos() {
-                port_rect.center().distance(pointer_pos) < DISTANCE_TO_CONNECT
-            } else {
-                false
-            };
+                // Check if the distance between the slot and the mouse is the distance to connect
+                let close_enough = pointer_pos
+                    .map(|pointer_pos| {
+                        slot_center.distance(pointer_pos) < DISTANCE_TO_CONNECT * zoom
+                    })
+                    .unwrap_or(false);
 
-            let port_color = if close_enough {
-                Color32::WHITE
-            } else {
-                port_type.data_type_color(user_state)
-            };
-            ui.painter()
-                .circle(port_rect.center(), 5.0, port_color, Stroke::none());
-
-            if resp.drag_started() {
-                if is_connected_input {
-                    let input = param_id.assume_input();
-                    let corresp_output = graph
-                        .connection(input)
-                        .expect("Connection data should be valid");
-                    responses.push(NodeResponse::DisconnectEvent {
-                        input: param_id.assume_input(),
-                        output: corresp_output,
-                    });
+                let slot_color = if close_enough {
+                    style.port_hovered
                 } else {
-                    responses.push(NodeResponse::ConnectEventStarted(node_id, param_id));
+                    port_color
+                };
+                ui.painter().circle(
+                    slot_center,
+                    style.port_radius * zoom,
+                    slot_color,
+                    Stroke::none(),
+                );
+
+                if resp.drag_started() {
+                    if let Some(output) = connection {
+                        responses.push(NodeResponse::DisconnectEvent {
+                            input: param_id.assume_input(),
+                            output: *output,
+                        });
+                    } else {
+                        responses.push(NodeResponse::ConnectEventStarted(node_id, param_id));
+                    }
                 }
             }
 
             if let Some((origin_node, origin_param)) = ongoing_drag {
-                if origin_node != node_id {
-                    // Don't allow self-loops
-                    if graph.any_param_type(origin_param).unwrap() == port_type
-                        && close_enough
-                        && ui.input().pointer.any_released()
-                    {
-                        match (param_id, origin_param) {
-                            (AnyParameterId::Input(input), AnyParameterId::Output(output))
-                            | (AnyParameterId::Output(output), AnyParameterId::Input(input)) => {
-                                responses.push(NodeResponse::ConnectEventEnded { input, output });
-                            }
-                            _ => { /* Ignore in-in or out-out connections */ }
+                // New connections are dropped on the last slot of the port.
+                let close_enough = pointer_pos
+                    .map(|pointer_pos| {
+                        slot_positions[slots.len() - 1].distance(pointer_pos)
+                            < DISTANCE_TO_CONNECT * zoom
+                    })
+                    .unwrap_or(false);
+
+                // Don't allow self-loops, in-in or out-out connections, and
+                // optionally cycles. The cycle check walks the graph, so it
+                // only runs for the port the connection is dropped on.
+                if close_enough
+                    && ui.input().pointer.any_released()
+                    && graph.any_param_type(origin_param).unwrap() == port_type
+                    && !connection_rejected(
+                        graph,
+                        origin_node,
+                        origin_param,
+                        param_id,
+                        reject_cycles,
+                    )
+                {
+                    match (param_id, origin_param) {
+                        (AnyParameterId::Input(input), AnyParameterId::Output(output))
+                        | (AnyParameterId::Output(output), AnyParameterId::Input(input)) => {
+                            responses.push(NodeResponse::ConnectEventEnded { input, output });
                         }
+                        _ => unreachable!("Rejected connections are filtered above"),
                     }
                 }
             }
 
-            port_locations.insert(param_id, port_rect.center());
+            port_locations.insert(param_id, port_pos);
         }
 
-        // Input ports
-        for ((_, param), port_height) in self.graph[self.node_id]
-            .inputs
-            .iter()
-            .zip(input_port_heights.into_iter())
-        {
-            let should_draw = match self.graph[*param].kind() {
-                InputParamKind::ConnectionOnly => true,
-                InputParamKind::ConstantOnly => false,
-                InputParamKind::ConnectionOrConstant => true,
-            };
+        if self.collapsed {
+            // All the ports are stacked at the edges of the title bar, so
+            // that connections still attach to the node. They are drawn as a
+            // single port on each side, which can't be interacted with.
+            let port_height = outer_rect.center().y;
+            let node = &self.graph[self.node_id];
+            let inputs = node
+                .input_ids()
+                .filter(|input| !matches!(self.graph[*input].kind(), InputParamKind::ConstantOnly))
+                .map(|input| (AnyParameterId::Input(input), port_left));
+            let outputs = node
+                .output_ids()
+                .map(|output| (AnyParameterId::Output(output), port_right));
+            let mut drawn_sides = SVec::<f32>::new();
+            for (param_id, port_x) in inputs.chain(outputs) {
+                let port_pos = pos2(port_x, port_height);
+                if !drawn_sides.contains(&port_x) {
+                    let port_type = self.graph.any_param_type(param_id).unwrap();
+                    ui.painter().circle_filled(
+                        port_pos,
+                        style.port_radius * zoom,
+                        port_type.data_type_color(user_state),
+                    );
+                    drawn_sides.push(port_x);
+                }
+                self.port_locations.insert(param_id, port_pos);
+            }
+        } else {
+            // Input ports
+            for ((_, param), port_height) in self.graph[self.node_id]
+                .inputs
+                .iter()
+                .zip(input_port_heights)
+            {
+                let should_draw = match self.graph[*param].kind() {
+                    InputParamKind::ConnectionOnly => true,
+                    InputParamKind::ConstantOnly => false,
+                    InputParamKind::ConnectionOrConstant => true,
+                };
+
+                if should_draw {
+                    let pos_left = pos2(port_left, port_height);
+                    draw_port(
+                        ui,
+                        self.graph,
+                        self.node_id,
+                        user_state,
+                        pos_left,
+                        &mut responses,
+                        AnyParameterId::Input(*param),
+                        self.port_locations,
+                        self.ongoing_drag,
+                        self.reject_cycles,
+                        style,
+                        zoom,
+                    );
+                }
+            }
 
-            if should_draw {
-                let pos_left = pos2(port_left, port_height);
+            // Output ports
+            for ((_, param), port_height) in self.graph[self.node_id]
+                .outputs
+                .iter()
+                .zip(output_port_heights)
+            {
+                let pos_right = pos2(port_right, port_height);
                 draw_port(
                     ui,
                     self.graph,
                     self.node_id,
                     user_state,
-                    pos_left,
+                    pos_right,
                     &mut responses,
-                    AnyParameterId::Input(*param),
+                    AnyParameterId::Output(*param),
                     self.port_locations,
                     self.ongoing_drag,
-                    self.graph.connection(*param).is_some(),
+                    self.reject_cycles,
+                    style,
+                    zoom,
                 );
             }
         }
 
-        // Output ports
-        for ((_, param), port_height) in self.graph[self.node_id]
-            .outputs
-            .iter()
-            .zip(output_port_heights.into_iter())
-        {
-            let pos_right = pos2(port_right, port_height);
-            draw_port(
-                ui,
-                self.graph,
-                self.node_id,
-                user_state,
-                pos_right,
-                &mut responses,
-                AnyParameterId::Output(*param),
-                self.port_locations,
-                self.ongoing_drag,
-                false,
-            );
-        }
-
         // Draw the background shape.
         // NOTE: This code is a bit more involved than it needs to be because egui
         // does not support drawing rectangles with asymmetrical round corners.
 
         let (shape, outline) = {
-            let rounding_radius = 4.0;
+            let rounding_radius = 4.0 * zoom;
             let rounding = Rounding::same(rounding_radius);
 
-            let titlebar_height = title_height + margin.y;
+            let titlebar_height = if self.collapsed {
+                outer_rect.height()
+            } else {
+                title_height + margin.y
+            };
             let titlebar_rect =
                 Rect::from_min_size(outer_rect.min, vec2(outer_rect.width(), titlebar_height));
             let titlebar = Shape::Rect(RectShape {
@@ -733,7 +1864,7 @@ where
                 fill: self.graph[self.node_id]
                     .user_data
                     .titlebar_color(ui, self.node_id, self.graph, user_state)
-                    .unwrap_or_else(|| background_color.lighten(0.8)),
+                    .unwrap_or_else(|| background_color.lighten(style.titlebar_tint)),
                 stroke: Stroke::none(),
             });
 
@@ -759,12 +1890,16 @@ where
                 stroke: Stroke::none(),
             });
 
-            let node_rect = titlebar_rect.union(body_rect).union(bottom_body_rect);
+            let node_rect = if self.collapsed {
+                titlebar_rect
+            } else {
+                titlebar_rect.union(body_rect).union(bottom_body_rect)
+            };
             let outline = if self.selected {
                 Shape::Rect(RectShape {
-                    rect: node_rect.expand(1.0),
+                    rect: node_rect.expand(zoom),
                     rounding,
-                    fill: Color32::WHITE.lighten(0.8),
+                    fill: style.selection_outline,
                     stroke: Stroke::none(),
                 })
             } else {
@@ -774,7 +1909,11 @@ where
             // Take note of the node rect, so the editor can use it later to compute intersections.
             self.node_rects.insert(self.node_id, node_rect);
 
-            (Shape::Vec(vec![titlebar, body, bottom_body]), outline)
+            if self.collapsed {
+                (titlebar, outline)
+            } else {
+                (Shape::Vec(vec![titlebar, body, bottom_body]), outline)
+            }
         };
 
         ui.painter().set(background_shape, shape);
@@ -789,9 +1928,53 @@ where
             user_state,
         );
 
-        if can_delete && Self::close_button(ui, outer_rect).clicked() {
+        if can_delete && Self::close_button(ui, outer_rect, style, zoom).clicked() {
             responses.push(NodeResponse::DeleteNodeUi(self.node_id));
         };
+        if Self::collapse_toggle(ui, outer_rect, self.collapsed, style, zoom).clicked() {
+            responses.push(NodeResponse::CollapseNode {
+                node: self.node_id,
+                collapsed: !self.collapsed,
+            });
+        }
+        if let Some(state) = self.evaluation_state {
+            Self::evaluation_state_indicator(ui, outer_rect, state, style, zoom);
+        }
+
+        // The resize handle comes before the node itself, to get the drags
+        if !self.collapsed {
+            let handle_size = Vec2::splat(NODE_RESIZE_HANDLE_SIZE * zoom);
+            let handle_rect = Rect::from_min_size(outer_rect.max - handle_size, handle_size);
+            let handle_response = ui.interact(
+                handle_rect,
+                Id::new((self.node_id, "resize")),
+                Sense::drag(),
+            );
+            let handle_color = if handle_response.hovered() || handle_response.dragged() {
+                text_color
+            } else {
+                text_color.linear_multiply(0.4)
+            };
+            let stroke = Stroke::new(1.5 * zoom, handle_color);
+            for offset in [0.3, 0.7] {
+                ui.painter().line_segment(
+                    [
+                        handle_rect.max - vec2(handle_size.x * offset, 0.0),
+                        handle_rect.max - vec2(0.0, handle_size.y * offset),
+                    ],
+                    stroke,
+                );
+            }
+
+            let drag_delta = handle_response.drag_delta() / zoom;
+            if drag_delta.length_sq() > 0.0 {
+                let constraints = self.graph[self.node_id].user_data.size_constraints();
+                responses.push(NodeResponse::ResizeNode {
+                    node: self.node_id,
+                    size: constraints.clamp(outer_rect.size() / zoom + drag_delta),
+                });
+            }
+        }
 
         let window_response = ui.interact(
             outer_rect,
@@ -800,7 +1983,7 @@ where
         );
 
         // Movement
-        let drag_delta = window_response.drag_delta();
+        let drag_delta = window_response.drag_delta() / zoom;
         if drag_delta.length_sq() > 0.0 {
             responses.push(NodeResponse::MoveNode {
                 node: self.node_id,
@@ -821,37 +2004,94 @@ where
         responses
     }
 
-    fn close_button(ui: &mut Ui, node_rect: Rect) -> Response {
+    /// Draws a dot left of the collapse toggle showing the evaluation state of
+    /// the node. It pulses while the node is being computed.
+    fn evaluation_state_indicator(
+        ui: &mut Ui,
+        node_rect: Rect,
+        state: NodeEvaluationState,
+        style: &NodeGraphStyle,
+        zoom: f32,
+    ) {
+        // Vertically centered with the close button
+        let position = pos2(
+            node_rect.right() - (22.0 + COLLAPSE_TOGGLE_SIZE + EVALUATION_STATE_SIZE / 2.0) * zoom,
+            node_rect.top() + 13.0 * zoom,
+        );
+        let radius = EVALUATION_STATE_SIZE * zoom / 3.0;
+        let color = match state {
+            NodeEvaluationState::Computing => {
+                ui.ctx().request_repaint();
+                let pulse = (ui.input().time * 4.0).sin() as f32 * 0.5 + 0.5;
+                style
+                    .evaluation_computing
+                    .linear_multiply(0.4 + 0.6 * pulse)
+            }
+            NodeEvaluationState::Done => style.evaluation_done,
+            NodeEvaluationState::Error => style.evaluation_error,
+        };
+        ui.painter().circle_filled(position, radius, color);
+    }
+
+    /// Draws the button collapsing and expanding the node, left of the close
+    /// button. The triangle points down when the node is expanded.
+    fn collapse_toggle(
+        ui: &mut Ui,
+        node_rect: Rect,
+        collapsed: bool,
+        style: &NodeGraphStyle,
+        zoom: f32,
+    ) -> Response {
+        // Vertically centered with the close button
+        let position = pos2(
+            node_rect.right() - (22.0 + COLLAPSE_TOGGLE_SIZE / 2.0) * zoom,
+            node_rect.top() + 13.0 * zoom,
+        );
+        let rect = Rect::from_center_size(position, Vec2::splat(COLLAPSE_TOGGLE_SIZE * zoom));
+        let resp = ui.allocate_rect(rect, Sense::click());
+
+        let color = if resp.hovered() {
+            style.button_hovered
+        } else {
+            style.button
+        };
+        let half = 4.0 * zoom;
+        let points = if collapsed {
+            vec![
+                position + vec2(-half / 2.0, -half),
+                position + vec2(half, 0.0),
+                position + vec2(-half / 2.0, half),
+            ]
+        } else {
+            vec![
+                position + vec2(-half, -half / 2.0),
+                position + vec2(half, -half / 2.0),
+                position + vec2(0.0, half),
+            ]
+        };
+        ui.painter()
+            .add(Shape::convex_polygon(points, color, Stroke::none()));
+
+        resp
+    }
+
+    fn close_button(ui: &mut Ui, node_rect: Rect, style: &NodeGraphStyle, zoom: f32) -> Response {
         // Measurements
-        let margin = 8.0;
-        let size = 10.0;
-        let stroke_width = 2.0;
+        let margin = 8.0 * zoom;
+        let size = 10.0 * zoom;
+        let stroke_width = 2.0 * zoom;
         let offs = margin + size / 2.0;
 
         let position = pos2(node_rect.right() - offs, node_rect.top() + offs);
         let rect = Rect::from_center_size(position, vec2(size, size));
         let resp = ui.allocate_rect(rect, Sense::click());
 
-        let dark_mode = ui.visuals().dark_mode;
         let color = if resp.clicked() {
-            if dark_mode {
-                color_from_hex("#ffffff").unwrap()
-            } else {
-                color_from_hex("#000000").unwrap()
-            }
+            style.button_clicked
         } else if resp.hovered() {
-            if dark_mode {
-                color_from_hex("#dddddd").unwrap()
-            } else {
-                color_from_hex("#222222").unwrap()
-            }
+            style.button_hovered
         } else {
-            #[allow(clippy::collapsible_else_if)]
-            if dark_mode {
-                color_from_hex("#aaaaaa").unwrap()
-            } else {
-                color_from_hex("#555555").unwrap()
-            }
+            style.button
         };
         let stroke = Stroke {
             width: stroke_width,
@@ -866,3 +2106,36 @@ where
         resp
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::{create_node, TestState};
+
+    #[test]
+    pub fn test_snap_selection() {
+        let mut state = TestState {
+            snap_to_grid: true,
+            grid_spacing: 20.0,
+            ..Default::default()
+        };
+        let (a, _, _) = create_node(&mut state, pos2(0.0, 0.0));
+        let (b, _, _) = create_node(&mut state, pos2(33.0, 47.0));
+        state.selected_nodes = vec![a, b];
+        state.history.seal();
+
+        // Each node snaps on its own, even when they are off grid from
+        // each other. Slow drags still move them.
+        let mut remainders = HashMap::new();
+        for _ in 0..3 {
+            state.drag_nodes(a, vec2(4.0, 0.0), &mut remainders);
+        }
+        assert_eq!(state.node_positions[a], pos2(20.0, 0.0));
+        assert_eq!(state.node_positions[b], pos2(40.0, 40.0));
+
+        // The drag is undone at once
+        state.undo();
+        assert_eq!(state.node_positions[a], pos2(0.0, 0.0));
+        assert_eq!(state.node_positions[b], pos2(33.0, 47.0));
+    }
+}
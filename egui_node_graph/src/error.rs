@@ -7,4 +7,10 @@ pub enum EguiGraphError {
 
     #[error("Parameter {0:?} was not found in the graph.")]
     InvalidParameterId(AnyParameterId),
+
+    #[error("The graph contains a cycle: {0:?}")]
+    CycleDetected(Vec<(InputId, OutputId)>),
+
+    #[error("Output {0:?} has no value. Its node has not been evaluated, or did not set it.")]
+    OutputNotEvaluated(OutputId),
 }
@@ -0,0 +1,544 @@
+use super::*;
+
+mod background;
+#[cfg(feature = "parallel")]
+mod parallel;
+
+pub use background::*;
+
+/// This trait can be implemented by the `NodeData` generic parameter of the
+/// [`Graph`] to evaluate the graph with a [`GraphEvaluator`]. Each node reads
+/// the values of its inputs and writes the values of its outputs through an
+/// [`EvaluationContext`].
+///
+/// When the evaluation depends on the node template, it's common to store the
+/// template in the `NodeData` and delegate to it.
+pub trait NodeEvaluatorTrait: Sized {
+    /// Must be set to the custom user `DataType` type
+    type DataType;
+    /// Must be set to the custom user `ValueType` type. The same type is used
+    /// for inline values and for the values flowing through connections.
+    type ValueType: Clone;
+    /// The error type returned by the evaluation. It must be possible to build
+    /// it from an [`EguiGraphError`], which is returned by the context when
+    /// accessing parameters that don't exist.
+    type Error: From<EguiGraphError>;
+
+    /// Computes the outputs of this node. Input values are read with
+    /// [`EvaluationContext::input`], and outputs are written with
+    /// [`EvaluationContext::set_output`].
+    fn evaluate(
+        &self,
+        ctx: &mut EvaluationContext<'_, Self, Self::DataType, Self::ValueType>,
+    ) -> Result<(), Self::Error>;
+}
+
+/// An error during the evaluation of a graph, along with the node that failed.
+#[derive(Debug, thiserror::Error)]
+#[error("Error evaluating node {node_id:?}: {error}")]
+pub struct EvaluationError<E> {
+    pub node_id: NodeId,
+    pub error: E,
+}
+
+/// The view of the graph given to a node while it is being evaluated.
+pub struct EvaluationContext<'a, NodeData, DataType, ValueType> {
+    graph: &'a Graph<NodeData, DataType, ValueType>,
+    node_id: NodeId,
+    cache: &'a SecondaryMap<OutputId, ValueType>,
+    produced: SVec<(OutputId, ValueType)>,
+}
+
+impl<'a, NodeData, DataType, ValueType: Clone>
+    EvaluationContext<'a, NodeData, DataType, ValueType>
+{
+    pub fn new(
+        graph: &'a Graph<NodeData, DataType, ValueType>,
+        node_id: NodeId,
+        cache: &'a SecondaryMap<OutputId, ValueType>,
+    ) -> Self {
+        Self {
+            graph,
+            node_id,
+            cache,
+            produced: SVec::new(),
+        }
+    }
+
+    /// The node being evaluated.
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn graph(&self) -> &'a Graph<NodeData, DataType, ValueType> {
+        self.graph
+    }
+
+    /// Returns the value of the input named `name`. This is the value of the
+    /// first connected output or, when nothing is connected, the inline value
+    /// of the parameter.
+    pub fn input(&self, name: &str) -> Result<ValueType, EguiGraphError> {
+        let input_id = self.graph[self.node_id].get_input(name)?;
+        match self.graph.connection(input_id) {
+            Some(output_id) => self.output_value(output_id),
+            None => Ok(self.graph[input_id].value.clone()),
+        }
+    }
+
+    /// Returns the values of all the outputs connected to the input named
+    /// `name`, in connection order. When nothing is connected, the inline value
+    /// of the parameter is returned as the only element.
+    pub fn inputs(&self, name: &str) -> Result<Vec<ValueType>, EguiGraphError> {
+        let input_id = self.graph[self.node_id].get_input(name)?;
+        let connections = self.graph.connections(input_id);
+        if connections.is_empty() {
+            Ok(vec![self.graph[input_id].value.clone()])
+        } else {
+            connections
+                .iter()
+                .map(|output_id| self.output_value(*output_id))
+                .collect()
+        }
+    }
+
+    /// Sets the value of the output named `name`.
+    pub fn set_output(&mut self, name: &str, value: ValueType) -> Result<(), EguiGraphError> {
+        let output_id = self.graph[self.node_id].get_output(name)?;
+        self.produced.retain(|(id, _)| *id != output_id);
+        self.produced.push((output_id, value));
+        Ok(())
+    }
+
+    fn output_value(&self, output_id: OutputId) -> Result<ValueType, EguiGraphError> {
+        self.cache
+            .get(output_id)
+            .cloned()
+            .ok_or(EguiGraphError::OutputNotEvaluated(output_id))
+    }
+
+    /// Consumes the context, returning the outputs set by the node.
+    pub fn into_outputs(self) -> SVec<(OutputId, ValueType)> {
+        self.produced
+    }
+}
+
+/// Evaluates the nodes of a [`Graph`] whose `NodeData` implements
+/// [`NodeEvaluatorTrait`]. The value of every output is cached, so each node is
+/// evaluated at most once until it's marked as dirty. This works on a plain
+/// graph and does not require the editor.
+///
+/// The evaluator is meant to be kept around across frames. After editing the
+/// graph, call [`GraphEvaluator::mark_dirty`] on the nodes that changed, or
+/// pass the responses of the editor to [`GraphEvaluator::handle_responses`].
+/// The next evaluation then only recomputes the dirty nodes it needs.
+#[derive(Debug, Clone)]
+pub struct GraphEvaluator<ValueType> {
+    cache: SecondaryMap<OutputId, ValueType>,
+    evaluated: SecondaryMap<NodeId, ()>,
+}
+
+impl<ValueType> Default for GraphEvaluator<ValueType> {
+    fn default() -> Self {
+        Self {
+            cache: SecondaryMap::default(),
+            evaluated: SecondaryMap::default(),
+        }
+    }
+}
+
+impl<ValueType: Clone> GraphEvaluator<ValueType> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluates `node_id`, after evaluating every node it depends on that
+    /// isn't cached yet.
+    pub fn evaluate_node<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_id: NodeId,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    {
+        self.evaluate_nodes(graph, [node_id])
+    }
+
+    /// Evaluates every node in the graph that isn't cached yet.
+    pub fn evaluate_all<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    {
+        self.evaluate_nodes(graph, graph.iter_nodes())
+    }
+
+    /// Evaluates the node producing `output_id` if needed, and returns the
+    /// value of the output.
+    pub fn evaluate_output<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        output_id: OutputId,
+    ) -> Result<&ValueType, EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    {
+        let node_id = graph[output_id].node;
+        self.evaluate_node(graph, node_id)?;
+        self.cache.get(output_id).ok_or_else(|| EvaluationError {
+            node_id,
+            error: EguiGraphError::OutputNotEvaluated(output_id).into(),
+        })
+    }
+
+    fn evaluate_nodes<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        roots: impl IntoIterator<Item = NodeId>,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    {
+        for node_id in self.pending_nodes(graph, roots)? {
+            // Don't leave stale values around if the node doesn't set all
+            // outputs, or fails.
+            self.forget_outputs(graph, node_id);
+            let outputs = evaluate_outputs(graph, node_id, &self.cache)
+                .map_err(|error| EvaluationError { node_id, error })?;
+            self.store_outputs(node_id, outputs);
+        }
+        Ok(())
+    }
+
+    /// Returns the nodes that need to be evaluated to get the values of
+    /// `roots`, sorted so that every node comes after its dependencies.
+    fn pending_nodes<NodeData, DataType>(
+        &self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        roots: impl IntoIterator<Item = NodeId>,
+    ) -> Result<Vec<NodeId>, EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    {
+        graph
+            .dependency_order(roots, |node| self.evaluated.contains_key(node))
+            .map_err(|err| EvaluationError {
+                node_id: match &err {
+                    EguiGraphError::CycleDetected(cycle) => graph[cycle[0].0].node,
+                    _ => unreachable!("Only cycles are reported when sorting nodes"),
+                },
+                error: err.into(),
+            })
+    }
+
+    fn forget_outputs<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_id: NodeId,
+    ) {
+        for output_id in graph[node_id].output_ids() {
+            self.cache.remove(output_id);
+        }
+    }
+
+    fn store_outputs(&mut self, node_id: NodeId, outputs: SVec<(OutputId, ValueType)>) {
+        for (output_id, value) in outputs {
+            self.cache.insert(output_id, value);
+        }
+        self.evaluated.insert(node_id, ());
+    }
+
+    /// Returns the cached value of `output_id`, if it has been evaluated. The
+    /// value of a dirty node is the one from its last evaluation.
+    pub fn output_value(&self, output_id: OutputId) -> Option<&ValueType> {
+        self.cache.get(output_id)
+    }
+
+    /// Returns true if `node_id` has been evaluated and isn't dirty.
+    pub fn is_evaluated(&self, node_id: NodeId) -> bool {
+        self.evaluated.contains_key(node_id)
+    }
+
+    /// Marks `node_id` and every node downstream of it as dirty, so they are
+    /// evaluated again the next time their values are requested.
+    pub fn mark_dirty<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_id: NodeId,
+    ) {
+        // Nodes are only evaluated after their dependencies, so everything
+        // downstream of a dirty node is already dirty.
+        if self.evaluated.remove(node_id).is_none() {
+            return;
+        }
+        for node_id in graph.downstream_nodes(node_id) {
+            self.evaluated.remove(node_id);
+        }
+    }
+
+    /// Marks as dirty the nodes affected by the responses of the graph editor:
+    /// nodes whose inline values changed, and nodes whose inputs were connected
+    /// or disconnected. Deleted nodes are forgotten.
+    ///
+    /// Must be called with the graph the responses were produced for, right
+    /// after drawing the editor.
+    pub fn handle_responses<NodeData, DataType, UserResponse, ResponseNodeData>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        responses: &[NodeResponse<UserResponse, ResponseNodeData>],
+    ) where
+        UserResponse: UserResponseTrait,
+        ResponseNodeData: NodeDataTrait,
+    {
+        for response in responses {
+            match response {
+                NodeResponse::ValueChanged { node, .. } => self.mark_dirty(graph, *node),
+                NodeResponse::ConnectEventEnded { input, .. }
+                | NodeResponse::DisconnectEvent { input, .. } => {
+                    if let Some(input) = graph.inputs.get(*input) {
+                        self.mark_dirty(graph, input.node);
+                    }
+                }
+                NodeResponse::DeleteNodeFull { node_id, node } => {
+                    self.evaluated.remove(*node_id);
+                    for (_, output_id) in &node.outputs {
+                        self.cache.remove(*output_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Forgets every cached value, so the next evaluation starts from scratch.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.evaluated.clear();
+    }
+}
+
+/// Runs the evaluation of a single node, reading the values of its inputs from
+/// `cache`, and returns the values of its outputs.
+fn evaluate_outputs<NodeData, DataType, ValueType>(
+    graph: &Graph<NodeData, DataType, ValueType>,
+    node_id: NodeId,
+    cache: &SecondaryMap<OutputId, ValueType>,
+) -> Result<SVec<(OutputId, ValueType)>, NodeData::Error>
+where
+    NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType>,
+    ValueType: Clone,
+{
+    let mut ctx = EvaluationContext::new(graph, node_id, cache);
+    graph[node_id].user_data.evaluate(&mut ctx)?;
+    Ok(ctx.into_outputs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    /// A tiny calculator, where values are plain numbers.
+    #[derive(Clone)]
+    pub(super) enum TestNode {
+        Constant,
+        Sum,
+        Fail,
+    }
+
+    impl NodeEvaluatorTrait for TestNode {
+        type DataType = ();
+        type ValueType = f32;
+        type Error = String;
+
+        fn evaluate(
+            &self,
+            ctx: &mut EvaluationContext<'_, Self, (), f32>,
+        ) -> Result<(), Self::Error> {
+            let value = match self {
+                TestNode::Constant => ctx.input("value")?,
+                TestNode::Sum => ctx.inputs("values")?.iter().sum(),
+                TestNode::Fail => return Err("Failed on purpose".into()),
+            };
+            Ok(ctx.set_output("out", value)?)
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub(super) struct TestResponse;
+    impl UserResponseTrait for TestResponse {}
+
+    impl NodeDataTrait for TestNode {
+        type Response = TestResponse;
+        type UserState = ();
+        type DataType = ();
+        type ValueType = f32;
+
+        fn bottom_ui(
+            &self,
+            _ui: &mut egui::Ui,
+            _node_id: NodeId,
+            _graph: &Graph<Self, (), f32>,
+            _user_state: &mut (),
+        ) -> Vec<NodeResponse<TestResponse, Self>> {
+            vec![]
+        }
+    }
+
+    impl From<EguiGraphError> for String {
+        fn from(err: EguiGraphError) -> Self {
+            err.to_string()
+        }
+    }
+
+    pub(super) type TestGraph = Graph<TestNode, (), f32>;
+
+    pub(super) fn add_node(
+        graph: &mut TestGraph,
+        node: TestNode,
+        value: f32,
+    ) -> (NodeId, InputId, OutputId) {
+        let wide = matches!(node, TestNode::Sum);
+        let node_id = graph.add_node("node".into(), node, |_, _| {});
+        let input = graph.add_wide_input_param(
+            node_id,
+            if wide { "values" } else { "value" }.into(),
+            (),
+            value,
+            InputParamKind::ConnectionOrConstant,
+            if wide { None } else { NonZeroU32::new(1) },
+            true,
+        );
+        let output = graph.add_output_param(node_id, "out".into(), ());
+        (node_id, input, output)
+    }
+
+    #[test]
+    pub fn test_evaluate_graph() {
+        let mut graph = TestGraph::new();
+        let (_, _, a) = add_node(&mut graph, TestNode::Constant, 1.0);
+        let (_, b_in, b) = add_node(&mut graph, TestNode::Constant, 2.0);
+        let (sum, sum_in, sum_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+        graph.add_connection(a, sum_in);
+        graph.add_connection(b, sum_in);
+        graph.add_connection(a, b_in);
+
+        let mut evaluator = GraphEvaluator::new();
+        assert_eq!(*evaluator.evaluate_output(&graph, sum_out).unwrap(), 2.0);
+        assert!(evaluator.is_evaluated(sum));
+
+        // Cached values are used until the evaluator is cleared
+        graph.remove_connection(b_in, a);
+        assert_eq!(*evaluator.evaluate_output(&graph, sum_out).unwrap(), 2.0);
+        evaluator.clear();
+        assert_eq!(*evaluator.evaluate_output(&graph, sum_out).unwrap(), 3.0);
+    }
+
+    #[test]
+    pub fn test_dirty_tracking() {
+        let mut graph = TestGraph::new();
+        let (a, a_in, a_out) = add_node(&mut graph, TestNode::Constant, 1.0);
+        let (b, _, b_out) = add_node(&mut graph, TestNode::Constant, 2.0);
+        let (sum, sum_in, sum_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+        graph.add_connection(a_out, sum_in);
+        graph.add_connection(b_out, sum_in);
+
+        let mut evaluator = GraphEvaluator::new();
+        evaluator.evaluate_all(&graph).unwrap();
+
+        graph[a_in].value = 5.0;
+        evaluator.mark_dirty(&graph, a);
+        assert!(!evaluator.is_evaluated(a));
+        assert!(!evaluator.is_evaluated(sum));
+        assert!(evaluator.is_evaluated(b));
+        // Stale values stay available until the node is evaluated again
+        assert_eq!(evaluator.output_value(sum_out), Some(&3.0));
+
+        // Only the dirty nodes upstream of the requested output are evaluated
+        assert_eq!(*evaluator.evaluate_output(&graph, a_out).unwrap(), 5.0);
+        assert!(!evaluator.is_evaluated(sum));
+        assert_eq!(*evaluator.evaluate_output(&graph, sum_out).unwrap(), 7.0);
+
+        graph.remove_connection(sum_in, b_out);
+        let responses: Vec<NodeResponse<TestResponse, TestNode>> =
+            vec![NodeResponse::DisconnectEvent {
+                output: b_out,
+                input: sum_in,
+            }];
+        evaluator.handle_responses(&graph, &responses);
+        assert!(!evaluator.is_evaluated(sum));
+        assert!(evaluator.is_evaluated(b));
+        assert_eq!(*evaluator.evaluate_output(&graph, sum_out).unwrap(), 5.0);
+    }
+
+    #[test]
+    pub fn test_evaluation_errors() {
+        let mut graph = TestGraph::new();
+        let (fail, _, fail_out) = add_node(&mut graph, TestNode::Fail, 0.0);
+        let (_, sum_in, sum_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+        graph.add_connection(fail_out, sum_in);
+
+        let err = GraphEvaluator::new()
+            .evaluate_output(&graph, sum_out)
+            .unwrap_err();
+        assert_eq!(err.node_id, fail);
+
+        // Close a loop between two constants
+        let (a, a_in, a_out) = add_node(&mut graph, TestNode::Constant, 0.0);
+        let (b, b_in, b_out) = add_node(&mut graph, TestNode::Constant, 0.0);
+        graph.add_connection(a_out, b_in);
+        graph.add_connection(b_out, a_in);
+        let err = GraphEvaluator::new().evaluate_node(&graph, a).unwrap_err();
+        assert!(err.node_id == a || err.node_id == b);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    pub fn test_parallel_evaluation() {
+        let mut graph = TestGraph::new();
+        let (_, total_in, total_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+        let mut sums = vec![];
+        for branch in 0..8 {
+            let (sum, sum_in, sum_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+            for i in 0..4 {
+                let (_, _, out) = add_node(&mut graph, TestNode::Constant, (branch * i) as f32);
+                graph.add_connection(out, sum_in);
+            }
+            graph.add_connection(sum_out, total_in);
+            sums.push(sum);
+        }
+
+        let mut sequential = GraphEvaluator::new();
+        let mut parallel = GraphEvaluator::new();
+        let expected = *sequential.evaluate_output(&graph, total_out).unwrap();
+        assert_eq!(expected, 168.0);
+        assert_eq!(
+            *parallel
+                .evaluate_output_parallel(&graph, total_out)
+                .unwrap(),
+            expected
+        );
+        for node_id in graph.iter_nodes() {
+            assert!(parallel.is_evaluated(node_id));
+        }
+
+        // Make two branches fail, the same error must be reported
+        for sum in &sums[2..4] {
+            let (_, _, fail_out) = add_node(&mut graph, TestNode::Fail, 0.0);
+            graph.add_connection(fail_out, graph[*sum].get_input("values").unwrap());
+        }
+        let mut sequential = GraphEvaluator::new();
+        let mut parallel = GraphEvaluator::new();
+        let expected = sequential.evaluate_all(&graph).unwrap_err();
+        let err = parallel.evaluate_all_parallel(&graph).unwrap_err();
+        assert_eq!(err.node_id, expected.node_id);
+        for node_id in graph.iter_nodes() {
+            assert_eq!(
+                parallel.is_evaluated(node_id),
+                sequential.is_evaluated(node_id)
+            );
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use super::*;
+
+/// The state of a node during a background evaluation, as tracked by a
+/// [`BackgroundEvaluator`]. It can be shown on each node by the editor through
+/// [`GraphEditorState::node_evaluation_states`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeEvaluationState {
+    /// The node is being evaluated.
+    Computing,
+    /// The node was evaluated, and its outputs are cached.
+    Done,
+    /// The evaluation of the node failed.
+    Error,
+}
+
+type EvaluatorGraph<NodeData> = Graph<
+    NodeData,
+    <NodeData as NodeEvaluatorTrait>::DataType,
+    <NodeData as NodeEvaluatorTrait>::ValueType,
+>;
+
+/// Messages sent by a background job to the UI thread.
+enum JobEvent<NodeData: NodeEvaluatorTrait> {
+    Started(NodeId),
+    Finished(NodeId),
+    /// The job is over, and hands the evaluator back. The result is `None`
+    /// when the job was cancelled.
+    Done {
+        evaluator: GraphEvaluator<NodeData::ValueType>,
+        result: Option<Result<(), EvaluationError<NodeData::Error>>>,
+    },
+}
+
+struct Job<NodeData: NodeEvaluatorTrait> {
+    roots: Vec<NodeId>,
+    events: mpsc::Receiver<JobEvent<NodeData>>,
+    cancelled: Arc<AtomicBool>,
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: std::thread::JoinHandle<()>,
+}
+
+/// Evaluates a [`Graph`] on a worker thread, so heavy graphs don't freeze the
+/// UI. Each evaluation runs on a snapshot of the graph, and reports the state
+/// of every node as it goes. On targets without threads (i.e. wasm), the
+/// evaluation runs on the calling thread instead.
+///
+/// The evaluator is meant to be kept around across frames: call
+/// [`BackgroundEvaluator::poll`] once per frame to receive progress, and pass
+/// the responses of the editor to [`BackgroundEvaluator::handle_responses`].
+/// Editing the graph cancels the running evaluation, which stops before
+/// evaluating its next node.
+pub struct BackgroundEvaluator<NodeData: NodeEvaluatorTrait> {
+    /// The evaluator holding the cache. It's moved to the worker thread while
+    /// a job runs.
+    evaluator: Option<GraphEvaluator<NodeData::ValueType>>,
+    job: Option<Job<NodeData>>,
+    node_states: SecondaryMap<NodeId, NodeEvaluationState>,
+    /// Nodes made dirty while a job was running. They are marked as dirty
+    /// once the evaluator is back.
+    invalidated: Vec<NodeId>,
+    /// The outputs of the nodes deleted while a job was running.
+    forgotten: Vec<(NodeId, Vec<OutputId>)>,
+    last_error: Option<EvaluationError<NodeData::Error>>,
+    /// The roots of the last evaluation, if it failed. They are not evaluated
+    /// again until the graph changes.
+    failed_roots: Option<Vec<NodeId>>,
+}
+
+impl<NodeData: NodeEvaluatorTrait> Default for BackgroundEvaluator<NodeData> {
+    fn default() -> Self {
+        Self {
+            evaluator: Some(GraphEvaluator::default()),
+            job: None,
+            node_states: SecondaryMap::default(),
+            invalidated: Vec::new(),
+            forgotten: Vec::new(),
+            last_error: None,
+            failed_roots: None,
+        }
+    }
+}
+
+impl<NodeData> BackgroundEvaluator<NodeData>
+where
+    NodeData: NodeEvaluatorTrait + Clone + Send + 'static,
+    NodeData::DataType: Clone + Send + 'static,
+    NodeData::ValueType: Send + 'static,
+    NodeData::Error: Send + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts evaluating `roots` and all their dependencies on a snapshot of
+    /// `graph`, unless they are already evaluated. Returns false when another
+    /// evaluation is still running, or was cancelled and hasn't stopped yet.
+    /// In that case nothing is started, and the call should be repeated on a
+    /// later frame.
+    ///
+    /// Roots whose evaluation failed are not evaluated again until the graph
+    /// is edited.
+    pub fn evaluate(
+        &mut self,
+        graph: &EvaluatorGraph<NodeData>,
+        roots: impl IntoIterator<Item = NodeId>,
+    ) -> bool {
+        let roots: Vec<NodeId> = roots.into_iter().collect();
+        if let Some(job) = &self.job {
+            return job.roots == roots && !job.cancelled.load(Ordering::Relaxed);
+        }
+        if self.failed_roots.as_ref() == Some(&roots) {
+            return true;
+        }
+        let evaluator = self.evaluator.take().expect("No job is running");
+        if roots.iter().all(|node_id| evaluator.is_evaluated(*node_id)) {
+            self.evaluator = Some(evaluator);
+            return true;
+        }
+
+        let (sender, events) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let run = {
+            let graph = graph.clone();
+            let roots = roots.clone();
+            let cancelled = cancelled.clone();
+            move || run_job(evaluator, &graph, roots, &cancelled, &sender)
+        };
+        self.last_error = None;
+        self.job = Some(Job {
+            roots,
+            events,
+            cancelled,
+            #[cfg(not(target_arch = "wasm32"))]
+            thread: std::thread::spawn(run),
+        });
+        #[cfg(target_arch = "wasm32")]
+        run();
+        true
+    }
+
+    /// Receives the progress of the running evaluation, if any. Must be called
+    /// once per frame. Returns true if the state of some node changed.
+    pub fn poll(&mut self) -> bool {
+        let job = match &self.job {
+            Some(job) => job,
+            None => return false,
+        };
+
+        let mut changed = false;
+        let mut done = None;
+        loop {
+            match job.events.try_recv() {
+                Ok(JobEvent::Started(node_id)) => {
+                    self.node_states
+                        .insert(node_id, NodeEvaluationState::Computing);
+                }
+                Ok(JobEvent::Finished(node_id)) => {
+                    self.node_states.insert(node_id, NodeEvaluationState::Done);
+                }
+                Ok(JobEvent::Done { evaluator, result }) => {
+                    done = Some((evaluator, result));
+                    break;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // The worker can only hang up without handing the evaluator
+                    // back if it panicked. Propagate the panic, like a
+                    // sequential evaluation would.
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if let Some(Err(panic)) = self.job.take().map(|job| job.thread.join()) {
+                        std::panic::resume_unwind(panic);
+                    }
+                    unreachable!("The worker always hands the evaluator back");
+                }
+            }
+            changed = true;
+        }
+
+        if let Some((mut evaluator, result)) = done {
+            let job = self.job.take().expect("The job just finished");
+            for node_id in self.invalidated.drain(..) {
+                evaluator.evaluated.remove(node_id);
+                self.node_states.remove(node_id);
+            }
+            for (node_id, outputs) in self.forgotten.drain(..) {
+                evaluator.evaluated.remove(node_id);
+                for output_id in outputs {
+                    evaluator.cache.remove(output_id);
+                }
+            }
+            // Nodes that were computing when the job was cancelled.
+            self.node_states
+                .retain(|_, state| *state != NodeEvaluationState::Computing);
+            if let Some(Err(err)) = result {
+                self.node_states
+                    .insert(err.node_id, NodeEvaluationState::Error);
+                self.last_error = Some(err);
+                self.failed_roots = Some(job.roots);
+            }
+            self.evaluator = Some(evaluator);
+        }
+        changed
+    }
+
+    /// Cancels the running evaluation, if any. The job stops before evaluating
+    /// its next node, and the values it computed so far stay cached.
+    pub fn cancel(&mut self) {
+        if let Some(job) = &self.job {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks `node_id` and every node downstream of it as dirty, cancelling the
+    /// running evaluation. See [`GraphEvaluator::mark_dirty`].
+    pub fn mark_dirty(&mut self, graph: &EvaluatorGraph<NodeData>, node_id: NodeId) {
+        self.cancel();
+        self.failed_roots = None;
+        let dirty = std::iter::once(node_id).chain(graph.downstream_nodes(node_id));
+        for node_id in dirty {
+            self.node_states.remove(node_id);
+            match &mut self.evaluator {
+                Some(evaluator) => {
+                    evaluator.evaluated.remove(node_id);
+                }
+                None => self.invalidated.push(node_id),
+            }
+        }
+    }
+
+    /// Marks as dirty the nodes affected by the responses of the graph editor,
+    /// cancelling the running evaluation when the graph was edited. See
+    /// [`GraphEvaluator::handle_responses`].
+    pub fn handle_responses<UserResponse, ResponseNodeData>(
+        &mut self,
+        graph: &EvaluatorGraph<NodeData>,
+        responses: &[NodeResponse<UserResponse, ResponseNodeData>],
+    ) where
+        UserResponse: UserResponseTrait,
+        ResponseNodeData: NodeDataTrait,
+    {
+        for response in responses {
+            match response {
+                NodeResponse::ValueChanged { node, .. } => self.mark_dirty(graph, *node),
+                NodeResponse::ConnectEventEnded { input, .. }
+                | NodeResponse::DisconnectEvent { input, .. } => {
+                    if let Some(input) = graph.inputs.get(*input) {
+                        self.mark_dirty(graph, input.node);
+                    }
+                }
+                NodeResponse::DeleteNodeFull { node_id, node } => {
+                    self.cancel();
+                    self.node_states.remove(*node_id);
+                    match &mut self.evaluator {
+                        Some(evaluator) => {
+                            evaluator.evaluated.remove(*node_id);
+                            for (_, output_id) in &node.outputs {
+                                evaluator.cache.remove(*output_id);
+                            }
+                        }
+                        None => self.forgotten.push((*node_id, node.output_ids().collect())),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns true while an evaluation is running. The UI should keep
+    /// repainting to receive its progress.
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// The state of every node touched by the evaluations so far. Nodes that
+    /// haven't been evaluated, or became dirty, are absent.
+    pub fn node_states(&self) -> &SecondaryMap<NodeId, NodeEvaluationState> {
+        &self.node_states
+    }
+
+    pub fn node_state(&self, node_id: NodeId) -> Option<NodeEvaluationState> {
+        self.node_states.get(node_id).copied()
+    }
+
+    /// Returns the cached value of `output_id`. Values are only available while
+    /// no evaluation is running.
+    pub fn output_value(&self, output_id: OutputId) -> Option<&NodeData::ValueType> {
+        self.evaluator.as_ref()?.output_value(output_id)
+    }
+
+    /// The error of the last evaluation, if it failed.
+    pub fn last_error(&self) -> Option<&EvaluationError<NodeData::Error>> {
+        self.last_error.as_ref()
+    }
+}
+
+impl<NodeData: NodeEvaluatorTrait> Drop for BackgroundEvaluator<NodeData> {
+    fn drop(&mut self) {
+        // Don't keep the worker busy for nothing
+        if let Some(job) = &self.job {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The body of a background job. Evaluates the pending nodes one by one,
+/// checking for cancellation in between, and always hands the evaluator back.
+fn run_job<NodeData: NodeEvaluatorTrait>(
+    mut evaluator: GraphEvaluator<NodeData::ValueType>,
+    graph: &EvaluatorGraph<NodeData>,
+    roots: Vec<NodeId>,
+    cancelled: &AtomicBool,
+    sender: &mpsc::Sender<JobEvent<NodeData>>,
+) {
+    let result = (|| -> Result<Option<()>, EvaluationError<NodeData::Error>> {
+        for node_id in evaluator.pending_nodes(graph, roots)? {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            // The receiver may be gone if the UI dropped the evaluator, in
+            // which case there is nobody left to report to.
+            let _ = sender.send(JobEvent::Started(node_id));
+            evaluator.forget_outputs(graph, node_id);
+            let outputs = evaluate_outputs(graph, node_id, &evaluator.cache)
+                .map_err(|error| EvaluationError { node_id, error })?;
+            evaluator.store_outputs(node_id, outputs);
+            let _ = sender.send(JobEvent::Finished(node_id));
+        }
+        Ok(Some(()))
+    })();
+    let _ = sender.send(JobEvent::Done {
+        evaluator,
+        result: result.transpose(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::*;
+
+    #[test]
+    pub fn test_background_evaluation() {
+        let mut graph = TestGraph::new();
+        let (a, a_in, a_out) = add_node(&mut graph, TestNode::Constant, 1.0);
+        let (sum, sum_in, sum_out) = add_node(&mut graph, TestNode::Sum, 0.0);
+        graph.add_connection(a_out, sum_in);
+
+        let mut evaluator = BackgroundEvaluator::new();
+        assert!(evaluator.evaluate(&graph, [sum]));
+        while evaluator.is_running() {
+            evaluator.poll();
+        }
+        assert_eq!(evaluator.output_value(sum_out), Some(&1.0));
+        assert_eq!(evaluator.node_state(a), Some(NodeEvaluationState::Done));
+        assert_eq!(evaluator.node_state(sum), Some(NodeEvaluationState::Done));
+
+        graph[a_in].value = 2.0;
+        let responses: Vec<NodeResponse<TestResponse, TestNode>> =
+            vec![NodeResponse::ValueChanged {
+                node: a,
+                input: a_in,
+            }];
+        evaluator.handle_responses(&graph, &responses);
+        assert_eq!(evaluator.node_state(sum), None);
+
+        // Make the sum fail by connecting a failing node
+        let (fail, _, fail_out) = add_node(&mut graph, TestNode::Fail, 0.0);
+        graph.add_connection(fail_out, sum_in);
+        evaluator.mark_dirty(&graph, sum);
+        assert!(evaluator.evaluate(&graph, [sum]));
+        while evaluator.is_running() {
+            evaluator.poll();
+        }
+        assert_eq!(evaluator.node_state(fail), Some(NodeEvaluationState::Error));
+        assert_eq!(evaluator.last_error().unwrap().node_id, fail);
+        assert_eq!(evaluator.output_value(a_out), Some(&2.0));
+    }
+}
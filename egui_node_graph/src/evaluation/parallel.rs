@@ -0,0 +1,227 @@
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use super::*;
+
+/// The outcome of evaluating a node on a worker thread. Panics are sent back
+/// to the calling thread, so they propagate like in a sequential evaluation.
+type WorkerResult<ValueType, Error> = thread::Result<Result<SVec<(OutputId, ValueType)>, Error>>;
+
+impl<ValueType: Clone + Send + Sync> GraphEvaluator<ValueType> {
+    /// Like [`GraphEvaluator::evaluate_node`], but evaluates independent
+    /// branches of the graph concurrently on a pool of worker threads.
+    pub fn evaluate_node_parallel<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_id: NodeId,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType> + Sync,
+        NodeData::Error: Send,
+        DataType: Sync,
+    {
+        self.evaluate_nodes_parallel(graph, [node_id])
+    }
+
+    /// Like [`GraphEvaluator::evaluate_all`], but evaluates independent
+    /// branches of the graph concurrently on a pool of worker threads.
+    pub fn evaluate_all_parallel<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType> + Sync,
+        NodeData::Error: Send,
+        DataType: Sync,
+    {
+        self.evaluate_nodes_parallel(graph, graph.iter_nodes())
+    }
+
+    /// Like [`GraphEvaluator::evaluate_output`], but evaluates independent
+    /// branches of the graph concurrently on a pool of worker threads.
+    pub fn evaluate_output_parallel<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        output_id: OutputId,
+    ) -> Result<&ValueType, EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType> + Sync,
+        NodeData::Error: Send,
+        DataType: Sync,
+    {
+        let node_id = graph[output_id].node;
+        self.evaluate_node_parallel(graph, node_id)?;
+        self.cache.get(output_id).ok_or_else(|| EvaluationError {
+            node_id,
+            error: EguiGraphError::OutputNotEvaluated(output_id).into(),
+        })
+    }
+
+    /// Evaluates the pending nodes needed by `roots`, sending each node to the
+    /// workers as soon as all of its dependencies are evaluated.
+    ///
+    /// To give the same results as a sequential evaluation, nodes are ranked by
+    /// their position in the sequential order. When a node fails, only the
+    /// nodes ranked before it keep being evaluated, and only their values are
+    /// stored in the cache. The reported error is the one of the first failing
+    /// node in that order.
+    fn evaluate_nodes_parallel<NodeData, DataType>(
+        &mut self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        roots: impl IntoIterator<Item = NodeId>,
+    ) -> Result<(), EvaluationError<NodeData::Error>>
+    where
+        NodeData: NodeEvaluatorTrait<DataType = DataType, ValueType = ValueType> + Sync,
+        NodeData::Error: Send,
+        DataType: Sync,
+    {
+        let order = self.pending_nodes(graph, roots)?;
+        if order.is_empty() {
+            return Ok(());
+        }
+
+        let rank: SecondaryMap<NodeId, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(rank, node_id)| (*node_id, rank))
+            .collect();
+        // Number of pending dependencies of each node, and the pending nodes
+        // depending on each node.
+        let mut waiting = SecondaryMap::<NodeId, usize>::new();
+        let mut dependents = SecondaryMap::<NodeId, SVec<NodeId>>::new();
+        let mut ready = vec![];
+        for node_id in order.iter().copied() {
+            let mut dependencies: SVec<NodeId> = graph
+                .predecessors(node_id)
+                .filter(|node| rank.contains_key(*node))
+                .collect();
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            for dependency in &dependencies {
+                dependents
+                    .entry(*dependency)
+                    .expect("Node should exist")
+                    .or_default()
+                    .push(node_id);
+            }
+            if dependencies.is_empty() {
+                ready.push(node_id);
+            }
+            waiting.insert(node_id, dependencies.len());
+        }
+
+        let threads = thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+            .min(order.len());
+        // Values produced by this evaluation, only stored in the cache at the
+        // end, once it's known which nodes a sequential evaluation would reach.
+        let mut produced = SecondaryMap::<OutputId, ValueType>::new();
+        let mut finished = vec![];
+        let mut failure: Option<(usize, EvaluationError<NodeData::Error>)> = None;
+
+        let (job_sender, job_receiver) =
+            mpsc::channel::<(NodeId, SecondaryMap<OutputId, ValueType>)>();
+        let job_receiver = Mutex::new(job_receiver);
+        thread::scope(|scope| {
+            let (result_sender, result_receiver) =
+                mpsc::channel::<(NodeId, WorkerResult<ValueType, NodeData::Error>)>();
+            for _ in 0..threads {
+                let job_receiver = &job_receiver;
+                let result_sender = result_sender.clone();
+                scope.spawn(move || loop {
+                    let job = job_receiver
+                        .lock()
+                        .expect("Workers don't panic while holding the lock")
+                        .recv();
+                    let (node_id, inputs) = match job {
+                        Ok(job) => job,
+                        // The evaluation is over
+                        Err(_) => break,
+                    };
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        evaluate_outputs(graph, node_id, &inputs)
+                    }));
+                    if result_sender.send((node_id, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(result_sender);
+
+            let mut in_flight = 0;
+            loop {
+                for node_id in ready.drain(..) {
+                    if matches!(&failure, Some((failed, _)) if *failed < rank[node_id]) {
+                        continue;
+                    }
+                    // Each job gets its own copy of the values it reads, so the
+                    // workers never wait on each other.
+                    let inputs = graph
+                        .incoming_connections(node_id)
+                        .filter_map(|(_, output_id)| {
+                            let value = if rank.contains_key(graph[output_id].node) {
+                                produced.get(output_id)
+                            } else {
+                                self.cache.get(output_id)
+                            };
+                            value.map(|value| (output_id, value.clone()))
+                        })
+                        .collect();
+                    job_sender
+                        .send((node_id, inputs))
+                        .expect("Workers run until the job channel is closed");
+                    in_flight += 1;
+                }
+                if in_flight == 0 {
+                    break;
+                }
+
+                let (node_id, result) = result_receiver
+                    .recv()
+                    .expect("Workers run until the job channel is closed");
+                in_flight -= 1;
+                match result {
+                    Ok(Ok(outputs)) => {
+                        produced.extend(outputs);
+                        finished.push(node_id);
+                        for dependent in dependents.get(node_id).into_iter().flatten() {
+                            waiting[*dependent] -= 1;
+                            if waiting[*dependent] == 0 {
+                                ready.push(*dependent);
+                            }
+                        }
+                    }
+                    Ok(Err(error)) => {
+                        if !matches!(&failure, Some((failed, _)) if *failed < rank[node_id]) {
+                            failure = Some((rank[node_id], EvaluationError { node_id, error }));
+                        }
+                    }
+                    Err(panic) => panic::resume_unwind(panic),
+                }
+            }
+            drop(job_sender);
+        });
+
+        let cutoff = failure.as_ref().map(|(failed, _)| *failed);
+        for node_id in finished {
+            if cutoff.map(|cutoff| rank[node_id] < cutoff).unwrap_or(true) {
+                self.forget_outputs(graph, node_id);
+                let outputs = graph[node_id]
+                    .output_ids()
+                    .filter_map(|output_id| Some((output_id, produced.remove(output_id)?)))
+                    .collect();
+                self.store_outputs(node_id, outputs);
+            }
+        }
+        match failure {
+            Some((_, err)) => {
+                self.forget_outputs(graph, err.node_id);
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::num::NonZeroU32;
+
 use super::*;
 
 #[cfg(feature = "persistence")]
@@ -35,6 +37,11 @@ fn shown_inline_default() -> bool {
     true
 }
 
+#[cfg(feature = "persistence")]
+fn max_connections_default() -> Option<NonZeroU32> {
+    NonZeroU32::new(1)
+}
+
 /// An input parameter. Input parameters are inside a node, and represent data
 /// that this node receives. Unlike their [`OutputParam`] counterparts, input
 /// parameters also display an inline widget which allows setting its "value".
@@ -55,6 +62,10 @@ pub struct InputParam<DataType, ValueType> {
     pub kind: InputParamKind,
     /// Back-reference to the node containing this parameter.
     pub node: NodeId,
+    /// How many incoming connections this input accepts. `None` means there
+    /// is no limit. Most inputs accept a single connection.
+    #[cfg_attr(feature = "persistence", serde(default = "max_connections_default"))]
+    pub max_connections: Option<NonZeroU32>,
     /// When true, the node is shown inline inside the node graph.
     #[cfg_attr(feature = "persistence", serde(default = "shown_inline_default"))]
     pub shown_inline: bool,
@@ -78,6 +89,10 @@ pub struct OutputParam<DataType> {
 /// crate to represent all the inner references in the data.
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "persistence",
+    serde(from = "SerializedGraph<NodeData, DataType, ValueType>")
+)]
 pub struct Graph<NodeData, DataType, ValueType> {
     /// The [`Node`]s of the graph
     pub nodes: SlotMap<NodeId, Node<NodeData>>,
@@ -85,7 +100,105 @@ pub struct Graph<NodeData, DataType, ValueType> {
     pub inputs: SlotMap<InputId, InputParam<DataType, ValueType>>,
     /// The [`OutputParam`]s of the graph
     pub outputs: SlotMap<OutputId, OutputParam<DataType>>,
-    // Connects the input of a node, to the output of its predecessor that
-    // produces it
-    pub connections: SecondaryMap<InputId, OutputId>,
+    // Connects the input of a node, to the outputs of its predecessors that
+    // produce it. Outputs are kept in connection order. Inputs without any
+    // connection have no entry.
+    //
+    // Prefer the connection methods of the graph over modifying this map
+    // directly, as they also keep the reverse index up to date. Otherwise,
+    // call `Graph::rebuild_outgoing` after modifying it.
+    pub connections: SecondaryMap<InputId, Vec<OutputId>>,
+    /// Reverse index of `connections`: the inputs consuming each output.
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub(crate) outgoing: SecondaryMap<OutputId, Vec<InputId>>,
+}
+
+#[cfg(feature = "persistence")]
+#[derive(Deserialize)]
+struct SerializedGraph<NodeData, DataType, ValueType> {
+    nodes: SlotMap<NodeId, Node<NodeData>>,
+    inputs: SlotMap<InputId, InputParam<DataType, ValueType>>,
+    outputs: SlotMap<OutputId, OutputParam<DataType>>,
+    connections: SecondaryMap<InputId, SerializedConnections>,
+}
+
+/// The connections of an input. Graphs saved before inputs could take several
+/// connections stored a single output per input.
+#[cfg(feature = "persistence")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedConnections {
+    Single(OutputId),
+    Many(Vec<OutputId>),
+}
+
+#[cfg(feature = "persistence")]
+impl<NodeData, DataType, ValueType> From<SerializedGraph<NodeData, DataType, ValueType>>
+    for Graph<NodeData, DataType, ValueType>
+{
+    fn from(serialized: SerializedGraph<NodeData, DataType, ValueType>) -> Self {
+        let mut graph = Self {
+            nodes: serialized.nodes,
+            inputs: serialized.inputs,
+            outputs: serialized.outputs,
+            connections: serialized
+                .connections
+                .into_iter()
+                .map(|(input, connections)| match connections {
+                    SerializedConnections::Single(output) => (input, vec![output]),
+                    SerializedConnections::Many(outputs) => (input, outputs),
+                })
+                .collect(),
+            outgoing: SecondaryMap::default(),
+        };
+        // The reverse index is not serialized, so it needs to be rebuilt.
+        graph.rebuild_outgoing();
+        graph
+    }
+}
+
+#[cfg(all(test, feature = "persistence"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_load_single_connection_format() {
+        let mut graph = Graph::<(), (), ()>::new();
+        let source = graph.add_node("source".into(), (), |_, _| {});
+        let output = graph.add_output_param(source, "out".into(), ());
+        let sink = graph.add_node("sink".into(), (), |_, _| {});
+        let input = graph.add_input_param(
+            sink,
+            "in".into(),
+            (),
+            (),
+            InputParamKind::ConnectionOnly,
+            true,
+        );
+        graph.add_connection(output, input);
+
+        // The layout of a graph saved with a single output per input
+        #[derive(Serialize)]
+        struct OldGraph<'a> {
+            nodes: &'a SlotMap<NodeId, Node<()>>,
+            inputs: &'a SlotMap<InputId, InputParam<(), ()>>,
+            outputs: &'a SlotMap<OutputId, OutputParam<()>>,
+            connections: SecondaryMap<InputId, OutputId>,
+        }
+        let old = OldGraph {
+            nodes: &graph.nodes,
+            inputs: &graph.inputs,
+            outputs: &graph.outputs,
+            connections: [(input, output)].into_iter().collect(),
+        };
+        let text = ron::to_string(&old).unwrap();
+        let loaded: Graph<(), (), ()> = ron::from_str(&text).unwrap();
+        assert_eq!(loaded.connections(input), &[output]);
+        assert_eq!(loaded.outgoing(output), &[input]);
+
+        // The current format still loads
+        let text = ron::to_string(&graph).unwrap();
+        let loaded: Graph<(), (), ()> = ron::from_str(&text).unwrap();
+        assert_eq!(loaded.connections(input), &[output]);
+    }
 }
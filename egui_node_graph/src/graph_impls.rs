@@ -1,3 +1,5 @@
+use std::num::NonZeroU32;
+
 use super::*;
 
 impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
@@ -7,6 +9,7 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
             inputs: SlotMap::default(),
             outputs: SlotMap::default(),
             connections: SecondaryMap::default(),
+            outgoing: SecondaryMap::default(),
         }
     }
 
@@ -32,6 +35,7 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
         node_id
     }
 
+    /// Adds an input parameter accepting a single incoming connection.
     pub fn add_input_param(
         &mut self,
         node_id: NodeId,
@@ -40,6 +44,30 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
         value: ValueType,
         kind: InputParamKind,
         shown_inline: bool,
+    ) -> InputId {
+        self.add_wide_input_param(
+            node_id,
+            name,
+            typ,
+            value,
+            kind,
+            NonZeroU32::new(1),
+            shown_inline,
+        )
+    }
+
+    /// Adds an input parameter accepting up to `max_connections` incoming
+    /// connections. `None` means there is no limit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_wide_input_param(
+        &mut self,
+        node_id: NodeId,
+        name: String,
+        typ: DataType,
+        value: ValueType,
+        kind: InputParamKind,
+        max_connections: Option<NonZeroU32>,
+        shown_inline: bool,
     ) -> InputId {
         let input_id = self.inputs.insert_with_key(|input_id| InputParam {
             id: input_id,
@@ -47,6 +75,7 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
             value,
             kind,
             node: node_id,
+            max_connections,
             shown_inline,
         });
         self.nodes[node_id].inputs.push((name, input_id));
@@ -56,15 +85,17 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
     pub fn remove_input_param(&mut self, param: InputId) {
         let node = self[param].node;
         self[node].inputs.retain(|(_, id)| *id != param);
+        self.remove_connections(param);
         self.inputs.remove(param);
-        self.connections.retain(|i, _| i != param);
     }
 
     pub fn remove_output_param(&mut self, param: OutputId) {
         let node = self[param].node;
         self[node].outputs.retain(|(_, id)| *id != param);
+        for input in self.outgoing.remove(param).unwrap_or_default() {
+            self.unlink_input(input, param);
+        }
         self.outputs.remove(param);
-        self.connections.retain(|_, o| *o != param);
     }
 
     pub fn add_output_param(&mut self, node_id: NodeId, name: String, typ: DataType) -> OutputId {
@@ -85,48 +116,218 @@ impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
     /// ids in the pair (the one on `node_id`'s end) will be invalid after
     /// calling this function.
     pub fn remove_node(&mut self, node_id: NodeId) -> (Node<NodeData>, Vec<(InputId, OutputId)>) {
-        let mut disconnect_events = vec![];
+        self.take_node(node_id, false)
+    }
 
-        self.connections.retain(|i, o| {
-            if self.outputs[*o].node == node_id || self.inputs[i].node == node_id {
-                disconnect_events.push((i, *o));
-                false
-            } else {
-                true
-            }
-        });
+    /// Like [`Graph::remove_node`], but the ids of the node and its parameters
+    /// stay reserved, so that [`Graph::reattach_node`] can add them back.
+    pub(crate) fn detach_node(
+        &mut self,
+        node_id: NodeId,
+    ) -> (Node<NodeData>, Vec<(InputId, OutputId)>) {
+        self.take_node(node_id, true)
+    }
+
+    /// Adds back a node removed with [`Graph::detach_node`], along with its
+    /// parameters, under the ids they had. Connections aren't restored.
+    pub(crate) fn reattach_node(
+        &mut self,
+        node: Node<NodeData>,
+        inputs: Vec<InputParam<DataType, ValueType>>,
+        outputs: Vec<OutputParam<DataType>>,
+    ) {
+        for input in inputs {
+            self.inputs.reattach(input.id, input);
+        }
+        for output in outputs {
+            self.outputs.reattach(output.id, output);
+        }
+        self.nodes.reattach(node.id, node);
+    }
+
+    fn take_node(
+        &mut self,
+        node_id: NodeId,
+        detach: bool,
+    ) -> (Node<NodeData>, Vec<(InputId, OutputId)>) {
+        let mut disconnect_events = vec![];
 
         // NOTE: Collect is needed because we can't borrow the input ids while
         // we remove them inside the loop.
         for input in self[node_id].input_ids().collect::<SVec<_>>() {
-            self.inputs.remove(input);
+            for output in self.remove_connections(input) {
+                disconnect_events.push((input, output));
+            }
+            if detach {
+                self.inputs.detach(input);
+            } else {
+                self.inputs.remove(input);
+            }
         }
         for output in self[node_id].output_ids().collect::<SVec<_>>() {
-            self.outputs.remove(output);
+            for input in self.outgoing.remove(output).unwrap_or_default() {
+                self.unlink_input(input, output);
+                disconnect_events.push((input, output));
+            }
+            if detach {
+                self.outputs.detach(output);
+            } else {
+                self.outputs.remove(output);
+            }
         }
-        let removed_node = self.nodes.remove(node_id).expect("Node should exist");
+        let removed_node = if detach {
+            self.nodes.detach(node_id)
+        } else {
+            self.nodes.remove(node_id)
+        }
+        .expect("Node should exist");
 
         (removed_node, disconnect_events)
     }
 
-    pub fn remove_connection(&mut self, input_id: InputId) -> Option<OutputId> {
-        self.connections.remove(input_id)
+    /// Removes the connection between `output_id` and `input_id`. Returns the
+    /// position the connection had among the connections of the input, or
+    /// `None` if the two parameters were not connected.
+    pub fn remove_connection(&mut self, input_id: InputId, output_id: OutputId) -> Option<usize> {
+        let position = self.unlink_input(input_id, output_id)?;
+        self.unlink_output(output_id, input_id);
+        Some(position)
+    }
+
+    /// Removes all the connections of `input_id`, returning the outputs it was
+    /// connected to, in order.
+    pub fn remove_connections(&mut self, input_id: InputId) -> Vec<OutputId> {
+        let outputs = self.connections.remove(input_id).unwrap_or_default();
+        for output in &outputs {
+            self.unlink_output(*output, input_id);
+        }
+        outputs
+    }
+
+    /// Removes `output` from the connections of `input`, without updating the
+    /// reverse index. Returns the position it had.
+    fn unlink_input(&mut self, input: InputId, output: OutputId) -> Option<usize> {
+        let outputs = self.connections.get_mut(input)?;
+        let position = outputs.iter().position(|o| *o == output)?;
+        outputs.remove(position);
+        if outputs.is_empty() {
+            self.connections.remove(input);
+        }
+        Some(position)
+    }
+
+    /// Removes `input` from the reverse index entry of `output`.
+    fn unlink_output(&mut self, output: OutputId, input: InputId) {
+        if let Some(inputs) = self.outgoing.get_mut(output) {
+            inputs.retain(|i| *i != input);
+            if inputs.is_empty() {
+                self.outgoing.remove(output);
+            }
+        }
     }
 
     pub fn iter_nodes(&self) -> impl Iterator<Item = NodeId> + '_ {
         self.nodes.iter().map(|(id, _)| id)
     }
 
+    /// Connects `output` to `input`, after any existing connection of the
+    /// input. When the input already holds as many connections as it accepts,
+    /// its last connection is replaced. Connecting the same pair twice does
+    /// nothing.
     pub fn add_connection(&mut self, output: OutputId, input: InputId) {
-        self.connections.insert(input, output);
+        let position = self.connections(input).len();
+        self.insert_connection(output, input, position);
     }
 
+    /// Like [`Graph::add_connection`], but places the connection at `position`
+    /// among the connections of `input`. The position is clamped to the valid
+    /// range.
+    pub fn insert_connection(&mut self, output: OutputId, input: InputId, position: usize) {
+        let max_connections = self[input].max_connections;
+        let outputs = self
+            .connections
+            .entry(input)
+            .expect("Input should exist")
+            .or_default();
+        if outputs.contains(&output) {
+            return;
+        }
+        let replaced = match max_connections {
+            Some(max) if outputs.len() >= max.get() as usize => {
+                outputs.split_off(max.get() as usize - 1)
+            }
+            _ => Vec::new(),
+        };
+        outputs.insert(position.min(outputs.len()), output);
+
+        for replaced_output in replaced {
+            self.unlink_output(replaced_output, input);
+        }
+        self.outgoing
+            .entry(output)
+            .expect("Output should exist")
+            .or_default()
+            .push(input);
+    }
+
+    /// Iterates over every connection in the graph, as input-output pairs.
     pub fn iter_connections(&self) -> impl Iterator<Item = (InputId, OutputId)> + '_ {
-        self.connections.iter().map(|(o, i)| (o, *i))
+        self.connections
+            .iter()
+            .flat_map(|(i, outputs)| outputs.iter().map(move |o| (i, *o)))
+    }
+
+    /// Returns all the outputs connected to `input`, in connection order.
+    pub fn connections(&self, input: InputId) -> &[OutputId] {
+        self.connections
+            .get(input)
+            .map(|outputs| outputs.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns all the inputs connected to `output`. This is a constant-time
+    /// lookup into a reverse index kept up to date by the graph.
+    pub fn outgoing(&self, output: OutputId) -> &[InputId] {
+        self.outgoing
+            .get(output)
+            .map(|inputs| inputs.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the nodes consuming any of the outputs of `node_id`, without
+    /// duplicates.
+    pub fn consumers(&self, node_id: NodeId) -> Vec<NodeId> {
+        let mut consumers = Vec::new();
+        for output in self[node_id].output_ids() {
+            for input in self.outgoing(output) {
+                let consumer = self[*input].node;
+                if !consumers.contains(&consumer) {
+                    consumers.push(consumer);
+                }
+            }
+        }
+        consumers
     }
 
+    /// Rebuilds the reverse index of connections from `connections`. Only
+    /// needed after modifying the `connections` field directly.
+    pub fn rebuild_outgoing(&mut self) {
+        self.outgoing.clear();
+        for (input, outputs) in self.connections.iter() {
+            for output in outputs {
+                self.outgoing
+                    .entry(*output)
+                    .expect("Output should exist")
+                    .or_default()
+                    .push(input);
+            }
+        }
+    }
+
+    /// Returns the first output connected to `input`, if any. Prefer
+    /// [`Graph::connections`] for inputs accepting multiple connections.
     pub fn connection(&self, input: InputId) -> Option<OutputId> {
-        self.connections.get(input).copied()
+        self.connections(input).first().copied()
     }
 
     pub fn any_param_type(&self, param: AnyParameterId) -> Result<&DataType, EguiGraphError> {
@@ -164,14 +365,14 @@ impl<NodeData> Node<NodeData> {
     pub fn inputs<'a, DataType, DataValue>(
         &'a self,
         graph: &'a Graph<NodeData, DataType, DataValue>,
-    ) -> impl Iterator<Item = &InputParam<DataType, DataValue>> + 'a {
+    ) -> impl Iterator<Item = &'a InputParam<DataType, DataValue>> + 'a {
         self.input_ids().map(|id| graph.get_input(id))
     }
 
     pub fn outputs<'a, DataType, DataValue>(
         &'a self,
         graph: &'a Graph<NodeData, DataType, DataValue>,
-    ) -> impl Iterator<Item = &OutputParam<DataType>> + 'a {
+    ) -> impl Iterator<Item = &'a OutputParam<DataType>> + 'a {
         self.output_ids().map(|id| graph.get_output(id))
     }
 
@@ -212,4 +413,88 @@ impl<DataType, ValueType> InputParam<DataType, ValueType> {
     pub fn node(&self) -> NodeId {
         self.node
     }
+
+    /// Returns true when this input accepts more than one connection.
+    pub fn is_wide(&self) -> bool {
+        !matches!(self.max_connections, Some(max) if max.get() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_wide_input_connections() {
+        let mut graph = Graph::<(), (), ()>::new();
+        let sources: Vec<OutputId> = (0..3)
+            .map(|_| {
+                let node = graph.add_node("source".into(), (), |_, _| {});
+                graph.add_output_param(node, "out".into(), ())
+            })
+            .collect();
+        let sink = graph.add_node("sink".into(), (), |_, _| {});
+        let single = graph.add_input_param(
+            sink,
+            "single".into(),
+            (),
+            (),
+            InputParamKind::ConnectionOnly,
+            true,
+        );
+        let wide = graph.add_wide_input_param(
+            sink,
+            "wide".into(),
+            (),
+            (),
+            InputParamKind::ConnectionOnly,
+            NonZeroU32::new(2),
+            true,
+        );
+
+        // Single inputs keep their old overwriting behavior
+        graph.add_connection(sources[0], single);
+        graph.add_connection(sources[1], single);
+        assert_eq!(graph.connections(single), &[sources[1]]);
+
+        // Wide inputs keep connections in order, up to their limit
+        graph.add_connection(sources[0], wide);
+        graph.add_connection(sources[0], wide);
+        graph.insert_connection(sources[1], wide, 0);
+        assert_eq!(graph.connections(wide), &[sources[1], sources[0]]);
+        graph.add_connection(sources[2], wide);
+        assert_eq!(graph.connections(wide), &[sources[1], sources[2]]);
+        assert_eq!(graph.iter_connections().count(), 3);
+        assert_eq!(graph.outgoing(sources[0]), &[] as &[InputId]);
+        assert_eq!(graph.outgoing(sources[1]), &[single, wide]);
+        assert_eq!(graph.consumers(graph[sources[1]].node), vec![sink]);
+
+        assert_eq!(graph.remove_connection(wide, sources[1]), Some(0));
+        assert_eq!(graph.remove_connection(wide, sources[1]), None);
+        assert_eq!(graph.connection(wide), Some(sources[2]));
+
+        let source_node = graph[sources[2]].node;
+        let (_, disconnected) = graph.remove_node(source_node);
+        assert_eq!(disconnected, vec![(wide, sources[2])]);
+        assert!(graph.connections(wide).is_empty());
+        assert!(!graph.connections.contains_key(wide));
+        assert_outgoing_consistent(&graph);
+
+        graph.remove_input_param(single);
+        assert!(graph.outgoing(sources[1]).is_empty());
+        assert_outgoing_consistent(&graph);
+    }
+
+    fn assert_outgoing_consistent(graph: &Graph<(), (), ()>) {
+        let mut rebuilt = graph.clone();
+        rebuilt.rebuild_outgoing();
+        for (output, _) in graph.outputs.iter() {
+            let mut expected = rebuilt.outgoing(output).to_vec();
+            let mut actual = graph.outgoing(output).to_vec();
+            expected.sort();
+            actual.sort();
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(graph.outgoing.len(), rebuilt.outgoing.len());
+    }
 }
@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+
+use super::*;
+
+impl<NodeData, DataType, ValueType> Graph<NodeData, DataType, ValueType> {
+    /// Returns the connections arriving at the inputs of `node_id`, as
+    /// input-output pairs.
+    pub fn incoming_connections(
+        &self,
+        node_id: NodeId,
+    ) -> impl Iterator<Item = (InputId, OutputId)> + '_ {
+        self[node_id].input_ids().flat_map(move |input| {
+            self.connections(input)
+                .iter()
+                .map(move |output| (input, *output))
+        })
+    }
+
+    /// Returns the nodes directly connected to the inputs of `node_id`. A node
+    /// may appear more than once if it is connected to several inputs.
+    pub fn predecessors(&self, node_id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.incoming_connections(node_id)
+            .map(|(_, output)| self[output].node)
+    }
+
+    /// Returns the nodes directly connected to the outputs of `node_id`. A node
+    /// may appear more than once if it is connected to several outputs.
+    pub fn successors(&self, node_id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self[node_id]
+            .output_ids()
+            .flat_map(move |output| self.outgoing(output))
+            .map(move |input| self[*input].node)
+    }
+
+    /// Returns every node `node_id` transitively depends on, in breadth-first
+    /// order. The node itself is not included.
+    pub fn upstream_nodes(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.breadth_first(node_id, |node| self.predecessors(node).collect())
+    }
+
+    /// Returns every node that transitively depends on `node_id`, in
+    /// breadth-first order. The node itself is not included.
+    pub fn downstream_nodes(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.breadth_first(node_id, |node| self.successors(node).collect())
+    }
+
+    fn breadth_first(
+        &self,
+        start: NodeId,
+        mut neighbours: impl FnMut(NodeId) -> SVec<NodeId>,
+    ) -> Vec<NodeId> {
+        let mut visited = SecondaryMap::<NodeId, ()>::new();
+        visited.insert(start, ());
+        let mut queue = VecDeque::from([start]);
+        let mut found = vec![];
+        while let Some(node) = queue.pop_front() {
+            for next in neighbours(node) {
+                if visited.insert(next, ()).is_none() {
+                    found.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        found
+    }
+
+    /// Returns true if connecting `output` to `input` would introduce a cycle
+    /// in the graph, including the case where both belong to the same node.
+    ///
+    /// The search walks upstream from the node of `output`, and stops as soon
+    /// as it reaches the node of `input`.
+    pub fn would_create_cycle(&self, output: OutputId, input: InputId) -> bool {
+        let (from, to) = (self[output].node, self[input].node);
+        let mut visited = SecondaryMap::<NodeId, ()>::new();
+        visited.insert(from, ());
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            for next in self.predecessors(node) {
+                if visited.insert(next, ()).is_none() {
+                    stack.push(next);
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns all the nodes of the graph sorted so that every node comes after
+    /// the nodes it depends on. When the graph contains a cycle, an
+    /// [`EguiGraphError::CycleDetected`] error is returned instead, containing
+    /// the connections that form one of the cycles.
+    pub fn topological_order(&self) -> Result<Vec<NodeId>, EguiGraphError> {
+        let mut in_degree: SecondaryMap<NodeId, usize> =
+            self.iter_nodes().map(|node| (node, 0)).collect();
+        let mut successors: SecondaryMap<NodeId, SVec<NodeId>> = SecondaryMap::new();
+        for (input, output) in self.iter_connections() {
+            let (from, to) = (self[output].node, self[input].node);
+            in_degree[to] += 1;
+            successors
+                .entry(from)
+                .expect("Node should exist")
+                .or_default()
+                .push(to);
+        }
+
+        let mut ready: VecDeque<NodeId> = in_degree
+            .iter()
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(node, _)| node)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+            for next in successors.get(node).into_iter().flatten() {
+                in_degree[*next] -= 1;
+                if in_degree[*next] == 0 {
+                    ready.push_back(*next);
+                }
+            }
+        }
+
+        if order.len() == self.nodes.len() {
+            Ok(order)
+        } else {
+            Err(EguiGraphError::CycleDetected(
+                self.find_cycle()
+                    .expect("Nodes left unsorted should be part of a cycle"),
+            ))
+        }
+    }
+
+    /// Searches the graph for a cycle. When one is found, returns the
+    /// connections forming it as input-output pairs, such that the output of
+    /// each connection belongs to the node of the input of the next one.
+    ///
+    /// The search is iterative, so it won't overflow the stack on deep graphs.
+    pub fn find_cycle(&self) -> Option<Vec<(InputId, OutputId)>> {
+        match self.dependency_order(self.iter_nodes(), |_| false) {
+            Err(EguiGraphError::CycleDetected(cycle)) => Some(cycle),
+            _ => None,
+        }
+    }
+
+    /// Returns `roots` and every node they transitively depend on, sorted so
+    /// that every node comes after the nodes it depends on. Nodes for which
+    /// `skip` returns true are left out, and their dependencies are not
+    /// explored unless another node needs them.
+    ///
+    /// When a cycle is reachable from the roots, an
+    /// [`EguiGraphError::CycleDetected`] error is returned instead, with the
+    /// connections forming the cycle as described in [`Graph::find_cycle`].
+    pub fn dependency_order(
+        &self,
+        roots: impl IntoIterator<Item = NodeId>,
+        mut skip: impl FnMut(NodeId) -> bool,
+    ) -> Result<Vec<NodeId>, EguiGraphError> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Visit {
+            InProgress,
+            Done,
+        }
+
+        struct Frame {
+            node: NodeId,
+            incoming: SVec<(InputId, OutputId)>,
+            /// The next incoming connection to explore. The one before it is
+            /// the connection that led to the frame above this one.
+            next: usize,
+        }
+
+        let mut visits = SecondaryMap::<NodeId, Visit>::new();
+        let mut order = vec![];
+        for root in roots {
+            if visits.contains_key(root) {
+                continue;
+            }
+            if skip(root) {
+                visits.insert(root, Visit::Done);
+                continue;
+            }
+
+            visits.insert(root, Visit::InProgress);
+            let mut stack = vec![Frame {
+                node: root,
+                incoming: self.incoming_connections(root).collect(),
+                next: 0,
+            }];
+
+            while let Some(frame) = stack.last_mut() {
+                let output = match frame.incoming.get(frame.next) {
+                    Some(&(_, output)) => output,
+                    None => {
+                        visits.insert(frame.node, Visit::Done);
+                        order.push(frame.node);
+                        stack.pop();
+                        continue;
+                    }
+                };
+                frame.next += 1;
+
+                let upstream = self[output].node;
+                match visits.get(upstream) {
+                    None if skip(upstream) => {
+                        visits.insert(upstream, Visit::Done);
+                    }
+                    None => {
+                        visits.insert(upstream, Visit::InProgress);
+                        stack.push(Frame {
+                            node: upstream,
+                            incoming: self.incoming_connections(upstream).collect(),
+                            next: 0,
+                        });
+                    }
+                    Some(Visit::InProgress) => {
+                        let cycle_start = stack
+                            .iter()
+                            .position(|frame| frame.node == upstream)
+                            .expect("Node in progress should be on the stack");
+                        return Err(EguiGraphError::CycleDetected(
+                            stack[cycle_start..]
+                                .iter()
+                                .map(|frame| frame.incoming[frame.next - 1])
+                                .collect(),
+                        ));
+                    }
+                    Some(Visit::Done) => {}
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestGraph = Graph<(), (), ()>;
+
+    /// Adds a node with one input and one output
+    fn add_node(graph: &mut TestGraph) -> (NodeId, InputId, OutputId) {
+        let node = graph.add_node("node".into(), (), |_, _| {});
+        let input = graph.add_input_param(
+            node,
+            "in".into(),
+            (),
+            (),
+            InputParamKind::ConnectionOnly,
+            true,
+        );
+        let output = graph.add_output_param(node, "out".into(), ());
+        (node, input, output)
+    }
+
+    #[test]
+    pub fn test_topological_order() {
+        let mut graph = TestGraph::new();
+        let (a, _, a_out) = add_node(&mut graph);
+        let (b, b_in, b_out) = add_node(&mut graph);
+        let (c, c_in, _) = add_node(&mut graph);
+        graph.add_connection(b_out, c_in);
+        graph.add_connection(a_out, b_in);
+
+        let order = graph.topological_order().unwrap();
+        assert_eq!(order, vec![a, b, c]);
+        assert!(graph.find_cycle().is_none());
+
+        assert_eq!(graph.upstream_nodes(c), vec![b, a]);
+        assert_eq!(graph.downstream_nodes(a), vec![b, c]);
+        assert!(graph.upstream_nodes(a).is_empty());
+
+        assert_eq!(
+            graph.dependency_order([c], |_| false).unwrap(),
+            vec![a, b, c]
+        );
+        assert_eq!(graph.dependency_order([c], |n| n == b).unwrap(), vec![c]);
+
+        assert!(graph.would_create_cycle(b_out, b_in));
+        assert!(graph.would_create_cycle(b_out, graph[a].get_input("in").unwrap()));
+        assert!(!graph.would_create_cycle(a_out, c_in));
+    }
+
+    #[test]
+    pub fn test_cycle_detection() {
+        let mut graph = TestGraph::new();
+        let (_, _, root_out) = add_node(&mut graph);
+        let (a, a_in, a_out) = add_node(&mut graph);
+        let (b, b_in, b_out) = add_node(&mut graph);
+        graph.add_connection(root_out, a_in);
+        graph.add_connection(a_out, b_in);
+
+        // Node `a` is fed by `root`, so replace that connection to close a loop
+        graph.add_connection(b_out, a_in);
+
+        let cycle = graph.find_cycle().unwrap();
+        assert_eq!(cycle.len(), 2);
+        assert!(cycle.contains(&(a_in, b_out)));
+        assert!(cycle.contains(&(b_in, a_out)));
+        for (i, (_, output)) in cycle.iter().enumerate() {
+            let (next_input, _) = cycle[(i + 1) % cycle.len()];
+            assert_eq!(graph[*output].node, graph[next_input].node);
+        }
+
+        assert!(matches!(
+            graph.topological_order(),
+            Err(EguiGraphError::CycleDetected(_))
+        ));
+        assert_eq!(graph.downstream_nodes(a), vec![b]);
+    }
+}
@@ -0,0 +1,1350 @@
+use std::collections::HashMap;
+
+use egui::{Pos2, Rect, Vec2};
+
+use super::*;
+
+/// Where a node created or deleted by an [`EditorCommand`] sits in the editor.
+#[derive(Clone, Copy, Debug)]
+pub struct NodePlacement {
+    pub node: NodeId,
+    /// The position of the node, in graph space.
+    pub position: Pos2,
+    /// The index of the node in [`GraphEditorState::node_order`].
+    pub order: usize,
+}
+
+/// A node created or deleted by an [`EditorCommand::ChangeNodes`], along with
+/// everything needed to add it back to the graph.
+#[derive(Clone, Debug)]
+pub struct NodeRecord<NodeData, DataType, ValueType> {
+    pub placement: NodePlacement,
+    pub node: Node<NodeData>,
+    pub inputs: Vec<InputParam<DataType, ValueType>>,
+    pub outputs: Vec<OutputParam<DataType>>,
+    /// The connections of the node's parameters, as input-output pairs, with
+    /// the position of each one among the connections of its input.
+    pub connections: Vec<(InputId, OutputId, usize)>,
+    /// The reroute knots of those connections.
+    pub knots: Vec<(InputId, OutputId, Vec<Pos2>)>,
+    /// Whether the history removed the node while keeping its ids reserved,
+    /// so that it comes back under the same ids.
+    pub(crate) detached: bool,
+}
+
+impl<NodeData, DataType, ValueType> NodeRecord<NodeData, DataType, ValueType>
+where
+    NodeData: Clone,
+    DataType: Clone,
+    ValueType: Clone,
+{
+    /// Records the current state of `node_id`, or returns `None` when the
+    /// node doesn't exist.
+    pub fn capture(
+        graph: &Graph<NodeData, DataType, ValueType>,
+        node_positions: &SecondaryMap<NodeId, Pos2>,
+        node_order: &[NodeId],
+        reroute_knots: &RerouteKnots,
+        node_id: NodeId,
+    ) -> Option<Self> {
+        let node = graph.nodes.get(node_id)?;
+        let mut connections = vec![];
+        for input in node.input_ids() {
+            for (position, output) in graph.connections(input).iter().enumerate() {
+                connections.push((input, *output, position));
+            }
+        }
+        for output in node.output_ids() {
+            for input in graph.outgoing(output) {
+                let position = graph
+                    .connections(*input)
+                    .iter()
+                    .position(|other| *other == output)
+                    .expect("The reverse index is up to date");
+                connections.push((*input, output, position));
+            }
+        }
+        let knots = connections
+            .iter()
+            .map(|(input, output, _)| (*input, *output, reroute_knots.get(*input, *output)))
+            .filter(|(_, _, knots)| !knots.is_empty())
+            .map(|(input, output, knots)| (input, output, knots.to_vec()))
+            .collect();
+        Some(Self {
+            placement: NodePlacement {
+                node: node_id,
+                position: node_positions.get(node_id).copied().unwrap_or(Pos2::ZERO),
+                order: node_order
+                    .iter()
+                    .position(|id| *id == node_id)
+                    .unwrap_or(node_order.len()),
+            },
+            node: node.clone(),
+            inputs: node.input_ids().map(|input| graph[input].clone()).collect(),
+            outputs: node
+                .output_ids()
+                .map(|output| graph[output].clone())
+                .collect(),
+            connections,
+            knots,
+            detached: false,
+        })
+    }
+}
+
+impl<NodeData, DataType, ValueType> NodeRecord<NodeData, DataType, ValueType> {
+    fn remap_ids(&mut self, remap: &IdRemap) {
+        remap.node(&mut self.placement.node);
+        remap.node(&mut self.node.id);
+        for (_, input) in &mut self.node.inputs {
+            remap.input(input);
+        }
+        for (_, output) in &mut self.node.outputs {
+            remap.output(output);
+        }
+        for param in &mut self.inputs {
+            remap.input(&mut param.id);
+            remap.node(&mut param.node);
+        }
+        for param in &mut self.outputs {
+            remap.output(&mut param.id);
+            remap.node(&mut param.node);
+        }
+        for (input, output, _) in &mut self.connections {
+            remap.input(input);
+            remap.output(output);
+        }
+        for (input, output, _) in &mut self.knots {
+            remap.input(input);
+            remap.output(output);
+        }
+    }
+}
+
+/// A comment frame created or deleted by an [`EditorCommand::ChangeFrames`].
+#[derive(Clone, Debug)]
+pub struct FrameRecord {
+    pub frame: FrameId,
+    pub data: CommentFrame,
+    /// Whether the history removed the frame while keeping its id reserved,
+    /// so that it comes back under the same id.
+    pub(crate) detached: bool,
+}
+
+impl FrameRecord {
+    pub fn new(frame: FrameId, data: CommentFrame) -> Self {
+        Self {
+            frame,
+            data,
+            detached: false,
+        }
+    }
+}
+
+/// The new ids given to the nodes, parameters and frames added back to the
+/// editor by an [`EditorCommand::ChangeNodes`] or
+/// [`EditorCommand::ChangeFrames`], when their own ids were freed by user
+/// code removing them outside of the history.
+#[derive(Default)]
+struct IdRemap {
+    nodes: HashMap<NodeId, NodeId>,
+    inputs: HashMap<InputId, InputId>,
+    outputs: HashMap<OutputId, OutputId>,
+    frames: HashMap<FrameId, FrameId>,
+}
+
+impl IdRemap {
+    fn frame(&self, id: &mut FrameId) {
+        if let Some(new_id) = self.frames.get(id) {
+            *id = *new_id;
+        }
+    }
+
+    fn node(&self, id: &mut NodeId) {
+        if let Some(new_id) = self.nodes.get(id) {
+            *id = *new_id;
+        }
+    }
+
+    fn input(&self, id: &mut InputId) {
+        if let Some(new_id) = self.inputs.get(id) {
+            *id = *new_id;
+        }
+    }
+
+    fn output(&self, id: &mut OutputId) {
+        if let Some(new_id) = self.outputs.get(id) {
+            *id = *new_id;
+        }
+    }
+}
+
+/// A reversible edit of a [`GraphEditorState`].
+#[derive(Clone, Debug)]
+pub enum EditorCommand<NodeData, DataType, ValueType> {
+    /// The connections of `input` went from `before` to `after`. The reroute
+    /// knots of the removed connections, which are lost with them, are kept
+    /// in `knots`.
+    SetConnections {
+        input: InputId,
+        before: Vec<OutputId>,
+        after: Vec<OutputId>,
+        knots: Vec<(OutputId, Vec<Pos2>)>,
+    },
+    /// The reroute knots of the connection from `output` to `input` went from
+    /// `before` to `after`.
+    SetKnots {
+        input: InputId,
+        output: OutputId,
+        before: Vec<Pos2>,
+        after: Vec<Pos2>,
+    },
+    /// The inline value of `input` went from `before` to `after`.
+    SetValue {
+        input: InputId,
+        before: ValueType,
+        after: ValueType,
+    },
+    /// `node` was collapsed, or expanded when `collapsed` is false.
+    SetCollapsed { node: NodeId, collapsed: bool },
+    /// The size of `node` went from `before` to `after`, in graph space. `None`
+    /// is the size the node takes from its contents.
+    ResizeNode {
+        node: NodeId,
+        before: Option<Vec2>,
+        after: Option<Vec2>,
+    },
+    /// Each of the nodes was moved by its delta, in graph space.
+    MoveNodes { moves: Vec<(NodeId, Vec2)> },
+    /// Nodes were created and / or deleted, along with their connections. The
+    /// ids of removed nodes stay reserved, so that they come back under the
+    /// same ids.
+    ChangeNodes {
+        created: Vec<NodeRecord<NodeData, DataType, ValueType>>,
+        deleted: Vec<NodeRecord<NodeData, DataType, ValueType>>,
+    },
+    /// Comment frames were created and / or deleted. Like nodes, removed
+    /// frames come back under the same ids.
+    ChangeFrames {
+        created: Vec<FrameRecord>,
+        deleted: Vec<FrameRecord>,
+    },
+    /// `frame` was moved by `delta`, in graph space, along with the `nodes`
+    /// inside of it.
+    MoveFrame {
+        frame: FrameId,
+        nodes: Vec<NodeId>,
+        delta: Vec2,
+    },
+    /// The area of `frame` went from `before` to `after`, in graph space.
+    ResizeFrame {
+        frame: FrameId,
+        before: Rect,
+        after: Rect,
+    },
+    /// The title of `frame` went from `before` to `after`.
+    RetitleFrame {
+        frame: FrameId,
+        before: String,
+        after: String,
+    },
+}
+
+impl<NodeData, DataType, ValueType> EditorCommand<NodeData, DataType, ValueType> {
+    /// Describes the change of the connections of `input` from `before` to
+    /// the ones it has in `graph`, keeping the knots of the removed ones.
+    pub fn set_connections(
+        graph: &Graph<NodeData, DataType, ValueType>,
+        reroute_knots: &RerouteKnots,
+        input: InputId,
+        before: Vec<OutputId>,
+    ) -> Self {
+        let after = graph.connections(input).to_vec();
+        let knots = before
+            .iter()
+            .filter(|output| !after.contains(output))
+            .map(|output| (*output, reroute_knots.get(input, *output).to_vec()))
+            .filter(|(_, knots)| !knots.is_empty())
+            .collect();
+        EditorCommand::SetConnections {
+            input,
+            before,
+            after,
+            knots,
+        }
+    }
+
+    fn remap_ids(&mut self, remap: &IdRemap) {
+        match self {
+            EditorCommand::SetConnections {
+                input,
+                before,
+                after,
+                knots,
+            } => {
+                remap.input(input);
+                for output in before
+                    .iter_mut()
+                    .chain(after)
+                    .chain(knots.iter_mut().map(|(output, _)| output))
+                {
+                    remap.output(output);
+                }
+            }
+            EditorCommand::SetKnots { input, output, .. } => {
+                remap.input(input);
+                remap.output(output);
+            }
+            EditorCommand::SetValue { input, .. } => remap.input(input),
+            EditorCommand::SetCollapsed { node, .. } | EditorCommand::ResizeNode { node, .. } => {
+                remap.node(node)
+            }
+            EditorCommand::MoveNodes { moves } => {
+                for (node, _) in moves {
+                    remap.node(node);
+                }
+            }
+            EditorCommand::ChangeNodes { created, deleted } => {
+                for record in created.iter_mut().chain(deleted) {
+                    record.remap_ids(remap);
+                }
+            }
+            EditorCommand::ChangeFrames { created, deleted } => {
+                for record in created.iter_mut().chain(deleted) {
+                    remap.frame(&mut record.frame);
+                }
+            }
+            EditorCommand::MoveFrame { frame, nodes, .. } => {
+                remap.frame(frame);
+                for node in nodes {
+                    remap.node(node);
+                }
+            }
+            EditorCommand::ResizeFrame { frame, .. }
+            | EditorCommand::RetitleFrame { frame, .. } => remap.frame(frame),
+        }
+    }
+
+    /// Folds `next` into this command when both edit the same thing, so that
+    /// continuous edits like drags are undone in a single step. Returns `next`
+    /// back when the commands can't be merged.
+    fn merge(&mut self, next: Self) -> Option<Self> {
+        match (self, next) {
+            (
+                EditorCommand::SetConnections {
+                    input,
+                    after,
+                    knots,
+                    ..
+                },
+                EditorCommand::SetConnections {
+                    input: next_input,
+                    after: next_after,
+                    knots: next_knots,
+                    ..
+                },
+            ) if *input == next_input => {
+                *after = next_after;
+                for (output, next) in next_knots {
+                    if !knots.iter().any(|(other, _)| *other == output) {
+                        knots.push((output, next));
+                    }
+                }
+            }
+            (
+                EditorCommand::SetKnots {
+                    input,
+                    output,
+                    after,
+                    ..
+                },
+                EditorCommand::SetKnots {
+                    input: next_input,
+                    output: next_output,
+                    after: next_after,
+                    ..
+                },
+            ) if *input == next_input && *output == next_output => *after = next_after,
+            (
+                EditorCommand::SetValue { input, after, .. },
+                EditorCommand::SetValue {
+                    input: next_input,
+                    after: next_after,
+                    ..
+                },
+            ) if *input == next_input => *after = next_after,
+            (
+                EditorCommand::ResizeNode { node, after, .. },
+                EditorCommand::ResizeNode {
+                    node: next_node,
+                    after: next_after,
+                    ..
+                },
+            ) if *node == next_node => *after = next_after,
+            (
+                EditorCommand::MoveNodes { moves },
+                EditorCommand::MoveNodes { moves: next_moves },
+            ) if moves
+                .iter()
+                .map(|(node, _)| node)
+                .eq(next_moves.iter().map(|(node, _)| node)) =>
+            {
+                for ((_, delta), (_, next_delta)) in moves.iter_mut().zip(next_moves) {
+                    *delta += next_delta;
+                }
+            }
+            (
+                EditorCommand::MoveFrame {
+                    frame,
+                    nodes,
+                    delta,
+                },
+                EditorCommand::MoveFrame {
+                    frame: next_frame,
+                    nodes: next_nodes,
+                    delta: next_delta,
+                },
+            ) if *frame == next_frame && *nodes == next_nodes => *delta += next_delta,
+            (
+                EditorCommand::ResizeFrame { frame, after, .. },
+                EditorCommand::ResizeFrame {
+                    frame: next_frame,
+                    after: next_after,
+                    ..
+                },
+            ) if *frame == next_frame => *after = next_after,
+            (
+                EditorCommand::RetitleFrame { frame, after, .. },
+                EditorCommand::RetitleFrame {
+                    frame: next_frame,
+                    after: next_after,
+                    ..
+                },
+            ) if *frame == next_frame => *after = next_after,
+            (_, next) => return Some(next),
+        }
+        None
+    }
+}
+
+/// The undo / redo history of a [`GraphEditorState`]. Each entry groups the
+/// commands recorded during a single user gesture, so that e.g. dragging a
+/// node or moving a connection to another port is undone in one step.
+///
+/// The editor records its own edits. Edits made by user code can be recorded
+/// with [`EditorHistory::push`]. Unrecorded edits are left alone by undo and
+/// redo, but commands referring to things they removed are skipped.
+#[derive(Clone, Debug)]
+pub struct EditorHistory<NodeData, DataType, ValueType> {
+    undo_stack: Vec<Vec<EditorCommand<NodeData, DataType, ValueType>>>,
+    redo_stack: Vec<Vec<EditorCommand<NodeData, DataType, ValueType>>>,
+    /// When false, new commands are added to the last entry of the undo stack.
+    sealed: bool,
+    /// The maximum number of entries in the undo stack.
+    limit: usize,
+}
+
+impl<NodeData, DataType, ValueType> Default for EditorHistory<NodeData, DataType, ValueType> {
+    fn default() -> Self {
+        Self {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            sealed: true,
+            limit: 100,
+        }
+    }
+}
+
+impl<NodeData, DataType, ValueType> EditorHistory<NodeData, DataType, ValueType> {
+    /// Records a command that has already been applied to the editor. The
+    /// command joins the current entry unless the entry was sealed.
+    pub fn push(&mut self, command: EditorCommand<NodeData, DataType, ValueType>) {
+        self.redo_stack.clear();
+        match self.undo_stack.last_mut() {
+            Some(entry) if !self.sealed => {
+                let last = entry.last_mut().expect("Entries are never empty");
+                if let Some(command) = last.merge(command) {
+                    entry.push(command);
+                }
+            }
+            _ => {
+                self.undo_stack.push(vec![command]);
+                if self.undo_stack.len() > self.limit {
+                    self.undo_stack.remove(0);
+                }
+                self.sealed = false;
+            }
+        }
+    }
+
+    /// Closes the current entry. The next command starts a new one.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Sets the maximum number of entries that can be undone. Defaults to 100.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        let excess = self.undo_stack.len().saturating_sub(limit);
+        self.undo_stack.drain(..excess);
+    }
+
+    /// Forgets every recorded command.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.sealed = true;
+    }
+}
+
+impl<NodeData, DataType, ValueType, NodeTemplate, UserState>
+    GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
+where
+    NodeData: NodeDataTrait + Clone,
+    NodeData::Response: UserResponseTrait,
+    DataType: Clone,
+    ValueType: Clone,
+{
+    /// Reverts the last entry of the history. Returns the responses describing
+    /// the changes, as if they had been made by the user.
+    pub fn undo(&mut self) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut responses = vec![];
+        self.stop_layout_animation();
+        self.history.seal();
+        if let Some(mut entry) = self.history.undo_stack.pop() {
+            for index in (0..entry.len()).rev() {
+                self.apply_command(&mut entry, index, false, &mut responses);
+            }
+            self.history.redo_stack.push(entry);
+        }
+        responses
+    }
+
+    /// Applies again the last entry reverted by [`GraphEditorState::undo`].
+    /// Returns the responses describing the changes.
+    pub fn redo(&mut self) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut responses = vec![];
+        self.stop_layout_animation();
+        self.history.seal();
+        if let Some(mut entry) = self.history.redo_stack.pop() {
+            for index in 0..entry.len() {
+                self.apply_command(&mut entry, index, true, &mut responses);
+            }
+            self.history.undo_stack.push(entry);
+        }
+        responses
+    }
+
+    /// Deletes `nodes` and their connections, recording it as a single entry
+    /// of the history. Returns a `DisconnectEvent` for every removed connection
+    /// followed by a `DeleteNodeFull` for every node.
+    pub fn delete_nodes(
+        &mut self,
+        nodes: &[NodeId],
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        self.delete_nodes_and_connections(nodes, &[])
+    }
+
+    /// Removes `connections`, given as input-output pairs, recording it as a
+    /// single entry of the history. Returns a `DisconnectEvent` for each of
+    /// them. Unlike dragging a connection off its input, this doesn't start a
+    /// new connection.
+    pub fn delete_connections(
+        &mut self,
+        connections: &[(InputId, OutputId)],
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        self.delete_nodes_and_connections(&[], connections)
+    }
+
+    /// Like [`GraphEditorState::delete_nodes`], but also removes
+    /// `connections` first. Everything is undone at once.
+    pub fn delete_nodes_and_connections(
+        &mut self,
+        nodes: &[NodeId],
+        connections: &[(InputId, OutputId)],
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        self.history.seal();
+        let responses = self.remove_nodes_and_connections(nodes, connections);
+        self.history.seal();
+        responses
+    }
+
+    /// Removes `frames`, recording it in the current entry of the history.
+    pub(crate) fn remove_frames(&mut self, frames: &[FrameId]) {
+        let mut deleted = vec![];
+        for frame_id in frames.iter().copied() {
+            if let Some(data) = self.frames.detach(frame_id) {
+                deleted.push(FrameRecord {
+                    frame: frame_id,
+                    data,
+                    detached: true,
+                });
+            }
+        }
+        self.selected_frames
+            .retain(|frame_id| !frames.contains(frame_id));
+        if !deleted.is_empty() {
+            self.history.push(EditorCommand::ChangeFrames {
+                created: vec![],
+                deleted,
+            });
+        }
+    }
+
+    /// The body of [`GraphEditorState::delete_nodes_and_connections`],
+    /// recording the removal in the current entry of the history.
+    pub(crate) fn remove_nodes_and_connections(
+        &mut self,
+        nodes: &[NodeId],
+        connections: &[(InputId, OutputId)],
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut responses = vec![];
+
+        let mut commands = vec![];
+        for (input, output) in connections.iter().copied() {
+            if !self.graph.inputs.contains_key(input)
+                || !self.graph.connections(input).contains(&output)
+            {
+                continue;
+            }
+            let previous = self.graph.connections(input).to_vec();
+            self.graph.remove_connection(input, output);
+            commands.push(EditorCommand::set_connections(
+                &self.graph,
+                &self.reroute_knots,
+                input,
+                previous,
+            ));
+            responses.push(NodeResponse::DisconnectEvent { input, output });
+        }
+        self.selected_connections
+            .retain(|(input, output)| !connections.contains(&(*input, *output)));
+
+        let mut deleted = vec![];
+        for node_id in nodes.iter().copied() {
+            if deleted
+                .iter()
+                .any(|record: &NodeRecord<_, _, _>| record.placement.node == node_id)
+            {
+                continue;
+            }
+            deleted.extend(NodeRecord::capture(
+                &self.graph,
+                &self.node_positions,
+                &self.node_order,
+                &self.reroute_knots,
+                node_id,
+            ));
+        }
+
+        for record in &mut deleted {
+            let node_id = record.placement.node;
+            let (node, disc_events) = self.graph.detach_node(node_id);
+            record.detached = true;
+            // Pass the disconnection responses first so user code can perform
+            // cleanup before node removal response.
+            responses.extend(
+                disc_events
+                    .into_iter()
+                    .map(|(input, output)| NodeResponse::DisconnectEvent { input, output }),
+            );
+            // Pass the full node as a response so library users can listen for
+            // it and get their user data.
+            responses.push(NodeResponse::DeleteNodeFull { node_id, node });
+            self.node_positions.remove(node_id);
+            // Make sure to not leave references to old nodes hanging
+            self.selected_nodes.retain(|id| *id != node_id);
+            self.node_order.retain(|id| *id != node_id);
+        }
+        let graph = &self.graph;
+        self.selected_connections
+            .retain(|(input, _)| graph.inputs.contains_key(*input));
+
+        if !deleted.is_empty() {
+            commands.push(EditorCommand::ChangeNodes {
+                created: vec![],
+                deleted,
+            });
+        }
+        for command in commands {
+            self.history.push(command);
+        }
+        responses
+    }
+
+    /// Records the creation of `nodes` as a new entry of the history.
+    pub(crate) fn record_created_nodes(&mut self, nodes: &[NodeId]) {
+        let created = nodes
+            .iter()
+            .filter_map(|node_id| {
+                NodeRecord::capture(
+                    &self.graph,
+                    &self.node_positions,
+                    &self.node_order,
+                    &self.reroute_knots,
+                    *node_id,
+                )
+            })
+            .collect();
+        self.history.seal();
+        self.history.push(EditorCommand::ChangeNodes {
+            created,
+            deleted: vec![],
+        });
+        self.history.seal();
+    }
+
+    fn apply_command(
+        &mut self,
+        entry: &mut [EditorCommand<NodeData, DataType, ValueType>],
+        index: usize,
+        forward: bool,
+        responses: &mut Vec<NodeResponse<NodeData::Response, NodeData>>,
+    ) {
+        // Interactions in progress may refer to things that no longer exist.
+        self.connection_in_progress = None;
+
+        let remap = match &mut entry[index] {
+            EditorCommand::SetConnections {
+                input,
+                before,
+                after,
+                knots,
+            } => {
+                let outputs = if forward { after } else { before };
+                if !self.graph.inputs.contains_key(*input) {
+                    return;
+                }
+                let current = self.graph.remove_connections(*input);
+                for output in current.iter().filter(|output| !outputs.contains(output)) {
+                    responses.push(NodeResponse::DisconnectEvent {
+                        output: *output,
+                        input: *input,
+                    });
+                }
+                for output in outputs {
+                    if !self.graph.outputs.contains_key(*output) {
+                        continue;
+                    }
+                    self.graph.add_connection(*output, *input);
+                    if !current.contains(output) {
+                        responses.push(NodeResponse::ConnectEventEnded {
+                            output: *output,
+                            input: *input,
+                        });
+                    }
+                }
+                if !forward {
+                    for (output, knots) in knots.iter() {
+                        if self.graph.connections(*input).contains(output) {
+                            self.reroute_knots.set(*input, *output, knots.clone());
+                        }
+                    }
+                }
+                None
+            }
+            EditorCommand::SetKnots {
+                input,
+                output,
+                before,
+                after,
+            } => {
+                if self.graph.inputs.contains_key(*input)
+                    && self.graph.connections(*input).contains(output)
+                {
+                    let knots = if forward { after } else { before };
+                    self.reroute_knots.set(*input, *output, knots.clone());
+                }
+                None
+            }
+            EditorCommand::SetValue {
+                input,
+                before,
+                after,
+            } => {
+                if let Some(param) = self.graph.inputs.get_mut(*input) {
+                    param.value = if forward { after } else { before }.clone();
+                    responses.push(NodeResponse::ValueChanged {
+                        node: param.node,
+                        input: *input,
+                    });
+                }
+                None
+            }
+            EditorCommand::SetCollapsed { node, collapsed } => {
+                let collapsed = *collapsed == forward;
+                if self.graph.nodes.contains_key(*node) {
+                    if collapsed {
+                        self.collapsed_nodes.insert(*node, ());
+                    } else {
+                        self.collapsed_nodes.remove(*node);
+                    }
+                    responses.push(NodeResponse::CollapseNode {
+                        node: *node,
+                        collapsed,
+                    });
+                }
+                None
+            }
+            EditorCommand::ResizeNode {
+                node,
+                before,
+                after,
+            } => {
+                if self.graph.nodes.contains_key(*node) {
+                    match if forward { after } else { before } {
+                        Some(size) => {
+                            self.node_sizes.insert(*node, *size);
+                            responses.push(NodeResponse::ResizeNode {
+                                node: *node,
+                                size: *size,
+                            });
+                        }
+                        None => {
+                            self.node_sizes.remove(*node);
+                        }
+                    }
+                }
+                None
+            }
+            EditorCommand::MoveNodes { moves } => {
+                for (node, delta) in moves.iter() {
+                    let drag_delta = if forward { *delta } else { -*delta };
+                    if let Some(position) = self.node_positions.get_mut(*node) {
+                        *position += drag_delta;
+                        responses.push(NodeResponse::MoveNode {
+                            node: *node,
+                            drag_delta,
+                        });
+                    }
+                }
+                None
+            }
+            EditorCommand::ChangeNodes { created, deleted } => {
+                let (removed, added) = if forward {
+                    (deleted, created)
+                } else {
+                    (created, deleted)
+                };
+                self.remove_records(removed, responses);
+                Some(self.add_records(added, responses))
+            }
+            EditorCommand::ChangeFrames { created, deleted } => {
+                let (removed, added) = if forward {
+                    (deleted, created)
+                } else {
+                    (created, deleted)
+                };
+                for record in removed.iter_mut() {
+                    if let Some(data) = self.frames.detach(record.frame) {
+                        record.data = data;
+                        record.detached = true;
+                    }
+                }
+                let frames = &self.frames;
+                self.selected_frames
+                    .retain(|frame_id| frames.contains_key(*frame_id));
+                let mut remap = IdRemap::default();
+                for record in added.iter_mut() {
+                    if record.detached {
+                        self.frames.reattach(record.frame, record.data.clone());
+                        record.detached = false;
+                    } else {
+                        let frame_id = self.frames.insert(record.data.clone());
+                        remap.frames.insert(record.frame, frame_id);
+                    }
+                }
+                Some(remap)
+            }
+            EditorCommand::MoveFrame {
+                frame,
+                nodes,
+                delta,
+            } => {
+                let drag_delta = if forward { *delta } else { -*delta };
+                if let Some(frame) = self.frames.get_mut(*frame) {
+                    frame.rect = frame.rect.translate(drag_delta);
+                }
+                for node in nodes {
+                    if let Some(position) = self.node_positions.get_mut(*node) {
+                        *position += drag_delta;
+                        responses.push(NodeResponse::MoveNode {
+                            node: *node,
+                            drag_delta,
+                        });
+                    }
+                }
+                None
+            }
+            EditorCommand::ResizeFrame {
+                frame,
+                before,
+                after,
+            } => {
+                if let Some(frame) = self.frames.get_mut(*frame) {
+                    frame.rect = if forward { *after } else { *before };
+                }
+                None
+            }
+            EditorCommand::RetitleFrame {
+                frame,
+                before,
+                after,
+            } => {
+                if let Some(frame) = self.frames.get_mut(*frame) {
+                    frame.title = if forward { after } else { before }.clone();
+                }
+                None
+            }
+        };
+        if let Some(remap) = remap {
+            self.remap_ids(&remap, entry);
+        }
+    }
+
+    /// Removes the nodes of `records` from the editor. The records are updated
+    /// first, so that adding the nodes back restores them as they were.
+    fn remove_records(
+        &mut self,
+        records: &mut [NodeRecord<NodeData, DataType, ValueType>],
+        responses: &mut Vec<NodeResponse<NodeData::Response, NodeData>>,
+    ) {
+        for record in records.iter_mut() {
+            if let Some(current) = NodeRecord::capture(
+                &self.graph,
+                &self.node_positions,
+                &self.node_order,
+                &self.reroute_knots,
+                record.placement.node,
+            ) {
+                *record = current;
+            }
+        }
+        for record in records.iter_mut() {
+            let node_id = record.placement.node;
+            if !self.graph.nodes.contains_key(node_id) {
+                continue;
+            }
+            let (node, disc_events) = self.graph.detach_node(node_id);
+            record.detached = true;
+            // Pass the disconnection responses first, like when deleting a node.
+            responses.extend(
+                disc_events
+                    .into_iter()
+                    .map(|(input, output)| NodeResponse::DisconnectEvent { input, output }),
+            );
+            responses.push(NodeResponse::DeleteNodeFull { node_id, node });
+            self.node_positions.remove(node_id);
+            self.node_order.retain(|id| *id != node_id);
+            self.selected_nodes.retain(|id| *id != node_id);
+        }
+        let graph = &self.graph;
+        self.selected_connections.retain(|(input, output)| {
+            graph.inputs.contains_key(*input) && graph.outputs.contains_key(*output)
+        });
+    }
+
+    /// Adds the nodes of `records` back to the editor, along with their
+    /// connections to the nodes that still exist. Returns the ids given to the
+    /// nodes that couldn't get their own back.
+    fn add_records(
+        &mut self,
+        records: &mut [NodeRecord<NodeData, DataType, ValueType>],
+        responses: &mut Vec<NodeResponse<NodeData::Response, NodeData>>,
+    ) -> IdRemap {
+        let mut remap = IdRemap::default();
+        let mut sorted: Vec<_> = records.iter_mut().collect();
+        // Placements are restored in increasing order
+        sorted.sort_by_key(|record| record.placement.order);
+        for record in sorted {
+            let node_id = if record.detached {
+                self.graph.reattach_node(
+                    record.node.clone(),
+                    record.inputs.clone(),
+                    record.outputs.clone(),
+                );
+                record.detached = false;
+                record.placement.node
+            } else {
+                self.insert_record(record, &mut remap)
+            };
+            self.node_positions
+                .insert(node_id, record.placement.position);
+            let order = record.placement.order.min(self.node_order.len());
+            self.node_order.insert(order, node_id);
+            responses.push(NodeResponse::CreatedNode(node_id));
+        }
+
+        let mut connections: Vec<_> = records
+            .iter()
+            .flat_map(|record| record.connections.iter().copied())
+            .map(|(mut input, mut output, position)| {
+                remap.input(&mut input);
+                remap.output(&mut output);
+                (input, output, position)
+            })
+            .collect();
+        // Connections are restored in increasing position, so that each one
+        // ends up where it was.
+        connections.sort_by_key(|(_, _, position)| *position);
+        for (input, output, position) in connections {
+            if !self.graph.inputs.contains_key(input)
+                || !self.graph.outputs.contains_key(output)
+                || self.graph.connections(input).contains(&output)
+            {
+                continue;
+            }
+            self.graph.insert_connection(output, input, position);
+            responses.push(NodeResponse::ConnectEventEnded { output, input });
+        }
+        for record in records {
+            for (mut input, mut output, knots) in record.knots.iter().cloned() {
+                remap.input(&mut input);
+                remap.output(&mut output);
+                if self.graph.connections(input).contains(&output) {
+                    self.reroute_knots.set(input, output, knots);
+                }
+            }
+        }
+        remap
+    }
+
+    /// Adds the node of `record` to the graph under new ids, which are added
+    /// to `remap`.
+    fn insert_record(
+        &mut self,
+        record: &NodeRecord<NodeData, DataType, ValueType>,
+        remap: &mut IdRemap,
+    ) -> NodeId {
+        let mut node = record.node.clone();
+        let node_id = self.graph.nodes.insert_with_key(|id| {
+            node.id = id;
+            node
+        });
+        remap.nodes.insert(record.placement.node, node_id);
+        for old in &record.inputs {
+            let mut param = old.clone();
+            param.node = node_id;
+            let input = self.graph.inputs.insert_with_key(|id| {
+                param.id = id;
+                param
+            });
+            remap.inputs.insert(old.id, input);
+        }
+        for old in &record.outputs {
+            let mut param = old.clone();
+            param.node = node_id;
+            let output = self.graph.outputs.insert_with_key(|id| {
+                param.id = id;
+                param
+            });
+            remap.outputs.insert(old.id, output);
+        }
+        let node = &mut self.graph.nodes[node_id];
+        for (_, input) in &mut node.inputs {
+            remap.input(input);
+        }
+        for (_, output) in &mut node.outputs {
+            remap.output(output);
+        }
+        node_id
+    }
+
+    /// Replaces the ids in `remap` everywhere in the editor and its history,
+    /// including the `entry` being applied.
+    fn remap_ids(
+        &mut self,
+        remap: &IdRemap,
+        entry: &mut [EditorCommand<NodeData, DataType, ValueType>],
+    ) {
+        for command in entry
+            .iter_mut()
+            .chain(self.history.undo_stack.iter_mut().flatten())
+            .chain(self.history.redo_stack.iter_mut().flatten())
+        {
+            command.remap_ids(remap);
+        }
+        for (old_id, new_id) in &remap.nodes {
+            if let Some(size) = self.node_sizes.remove(*old_id) {
+                self.node_sizes.insert(*new_id, size);
+            }
+            if self.collapsed_nodes.remove(*old_id).is_some() {
+                self.collapsed_nodes.insert(*new_id, ());
+            }
+            if self.pinned_nodes.remove(*old_id).is_some() {
+                self.pinned_nodes.insert(*new_id, ());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    pub(crate) struct TestResponse;
+    impl UserResponseTrait for TestResponse {}
+
+    #[derive(Clone)]
+    pub(crate) struct TestNode;
+
+    impl NodeDataTrait for TestNode {
+        type Response = TestResponse;
+        type UserState = ();
+        type DataType = ();
+        type ValueType = f32;
+
+        fn bottom_ui(
+            &self,
+            _ui: &mut egui::Ui,
+            _node_id: NodeId,
+            _graph: &Graph<Self, (), f32>,
+            _user_state: &mut (),
+        ) -> Vec<NodeResponse<TestResponse, Self>> {
+            vec![]
+        }
+    }
+
+    pub(crate) type TestState = GraphEditorState<TestNode, (), f32, (), ()>;
+
+    /// Adds a node the way the node finder does, recording it in the history
+    pub(crate) fn create_node(
+        state: &mut TestState,
+        position: Pos2,
+    ) -> (NodeId, InputId, OutputId) {
+        let node = state.graph.add_node("node".into(), TestNode, |_, _| {});
+        let input = state.graph.add_input_param(
+            node,
+            "in".into(),
+            (),
+            0.0,
+            InputParamKind::ConnectionOrConstant,
+            true,
+        );
+        let output = state.graph.add_output_param(node, "out".into(), ());
+        state.node_positions.insert(node, position);
+        state.node_order.push(node);
+        state.record_created_nodes(&[node]);
+        (node, input, output)
+    }
+
+    #[test]
+    pub fn test_undo_redo() {
+        let mut state = TestState::default();
+        let (a, _, a_out) = create_node(&mut state, Pos2::ZERO);
+        let (b, b_in, _) = create_node(&mut state, Pos2::new(100.0, 0.0));
+
+        state.graph.add_connection(a_out, b_in);
+        state.history.push(EditorCommand::set_connections(
+            &state.graph,
+            &state.reroute_knots,
+            b_in,
+            vec![],
+        ));
+        state.history.seal();
+
+        // Two moves during the same drag are undone at once
+        for _ in 0..2 {
+            state.node_positions[b] += Vec2::new(5.0, 0.0);
+            state.history.push(EditorCommand::MoveNodes {
+                moves: vec![(b, Vec2::new(5.0, 0.0))],
+            });
+        }
+        state.history.seal();
+
+        // Delete the first node, like the close button does
+        state.delete_nodes(&[a]);
+        assert!(state.graph.connections(b_in).is_empty());
+
+        // The node comes back with the same ids
+        let responses = state.undo();
+        assert!(responses
+            .iter()
+            .any(|response| matches!(response, NodeResponse::CreatedNode(node) if *node == a)));
+        assert_eq!(state.node_order, vec![a, b]);
+        assert_eq!(state.graph.connections(b_in), &[a_out]);
+        assert_eq!(state.node_positions[a], Pos2::ZERO);
+
+        state.undo();
+        assert_eq!(state.node_positions[b], Pos2::new(100.0, 0.0));
+        state.undo();
+        assert!(state.graph.connections(b_in).is_empty());
+
+        state.redo();
+        state.redo();
+        assert_eq!(state.node_positions[b], Pos2::new(110.0, 0.0));
+        assert_eq!(state.graph.connections(b_in), &[a_out]);
+        state.redo();
+        assert!(!state.graph.nodes.contains_key(a));
+        assert!(!state.history.can_redo());
+
+        // Undo everything, including the creation of both nodes
+        while state.history.can_undo() {
+            state.undo();
+        }
+        assert!(state.graph.nodes.is_empty());
+        assert!(state.node_order.is_empty());
+        state.redo();
+        assert_eq!(state.graph.iter_nodes().collect::<Vec<_>>(), vec![a]);
+    }
+
+    #[test]
+    pub fn test_undo_keeps_other_edits() {
+        let mut state = TestState::default();
+        let (a, _, _) = create_node(&mut state, Pos2::ZERO);
+        let (b, b_in, b_out) = create_node(&mut state, Pos2::new(100.0, 0.0));
+        state.graph.add_connection(b_out, b_in);
+        state.delete_nodes(&[a]);
+
+        // A node added outside of the history survives undo and redo
+        let extra = state.graph.add_node("extra".into(), TestNode, |_, _| {});
+        state.undo();
+        state.redo();
+        assert!(state.graph.nodes.contains_key(extra));
+        assert!(state.graph.nodes.contains_key(b));
+        assert_eq!(state.graph.connections(b_in), &[b_out]);
+        assert_eq!(state.graph.iter_nodes().count(), 2);
+    }
+
+    #[test]
+    pub fn test_undo_knots() {
+        let mut state = TestState::default();
+        let (a, _, a_out) = create_node(&mut state, Pos2::ZERO);
+        let (_, b_in, _) = create_node(&mut state, Pos2::new(100.0, 0.0));
+        state.graph.add_connection(a_out, b_in);
+
+        let knot = Pos2::new(50.0, 50.0);
+        state.reroute_knots.insert(b_in, a_out, 0, knot);
+        state.history.push(EditorCommand::SetKnots {
+            input: b_in,
+            output: a_out,
+            before: vec![],
+            after: vec![knot],
+        });
+        state.history.seal();
+
+        // Removing the connection, then the node, keeps the knots around
+        state.delete_connections(&[(b_in, a_out)]);
+        state.reroute_knots.retain(|_, _| false);
+        state.undo();
+        assert_eq!(state.reroute_knots.get(b_in, a_out), &[knot]);
+
+        state.delete_nodes(&[a]);
+        state.reroute_knots.retain(|_, _| false);
+        state.undo();
+        assert_eq!(state.reroute_knots.get(b_in, a_out), &[knot]);
+
+        state.undo();
+        assert!(state.reroute_knots.get(b_in, a_out).is_empty());
+        state.redo();
+        assert_eq!(state.reroute_knots.get(b_in, a_out), &[knot]);
+    }
+
+    #[test]
+    pub fn test_undo_collapse() {
+        let mut state = TestState::default();
+        let (a, _, _) = create_node(&mut state, Pos2::ZERO);
+        state.collapsed_nodes.insert(a, ());
+        state.history.push(EditorCommand::SetCollapsed {
+            node: a,
+            collapsed: true,
+        });
+        state.history.seal();
+
+        state.undo();
+        assert!(!state.collapsed_nodes.contains_key(a));
+        state.redo();
+        assert!(state.collapsed_nodes.contains_key(a));
+    }
+
+    #[test]
+    pub fn test_undo_resize() {
+        let mut state = TestState::default();
+        let (a, _, _) = create_node(&mut state, Pos2::ZERO);
+        // Resizing over several frames is undone at once
+        for width in [210.0, 220.0] {
+            let before = state.node_sizes.insert(a, Vec2::new(width, 100.0));
+            state.history.push(EditorCommand::ResizeNode {
+                node: a,
+                before,
+                after: Some(Vec2::new(width, 100.0)),
+            });
+        }
+        state.history.seal();
+
+        state.undo();
+        assert!(!state.node_sizes.contains_key(a));
+        state.redo();
+        assert_eq!(state.node_sizes[a], Vec2::new(220.0, 100.0));
+    }
+
+    #[test]
+    pub fn test_redo_node_removed_by_user_code() {
+        let mut state = TestState::default();
+        let (a, _, _) = create_node(&mut state, Pos2::ZERO);
+        let (b, b_in, _) = create_node(&mut state, Pos2::new(100.0, 0.0));
+        state.node_sizes.insert(b, Vec2::new(300.0, 100.0));
+        state.graph.remove_node(a);
+        state.node_order.retain(|id| *id != a);
+
+        // The ids freed by user code can't be reused, so that node comes back
+        // under new ones, while the other one keeps its own
+        state.undo();
+        state.undo();
+        state.redo();
+        state.redo();
+        assert_eq!(state.graph.iter_nodes().count(), 2);
+        assert!(!state.graph.nodes.contains_key(a));
+        assert_eq!(state.graph[b_in].node, b);
+        assert_eq!(state.node_sizes[b], Vec2::new(300.0, 100.0));
+        assert_eq!(state.node_order.len(), 2);
+    }
+
+    #[test]
+    pub fn test_undo_frames() {
+        let mut state = TestState::default();
+        let (a, _, _) = create_node(&mut state, Pos2::new(20.0, 40.0));
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::splat(300.0));
+        let frame =
+            state
+                .frames
+                .insert(CommentFrame::new("Group".into(), rect, egui::Color32::RED));
+
+        // Deleting a selection with a node and a frame is undone at once,
+        // bringing both back under their ids
+        state.selected_nodes = vec![a];
+        state.selected_frames = vec![frame];
+        state.delete_selection(&mut ());
+        assert!(state.frames.is_empty());
+        assert!(state.graph.nodes.is_empty());
+        state.undo();
+        assert!(state.graph.nodes.contains_key(a));
+        assert_eq!(state.frames[frame].title, "Group");
+        state.redo();
+        assert!(state.frames.is_empty());
+        state.undo();
+
+        // Moving the frame moves the nodes inside of it back too
+        let delta = Vec2::new(50.0, 0.0);
+        state.frames[frame].rect = rect.translate(delta);
+        state.node_positions[a] += delta;
+        state.history.push(EditorCommand::MoveFrame {
+            frame,
+            nodes: vec![a],
+            delta,
+        });
+        state.history.seal();
+        state.undo();
+        assert_eq!(state.frames[frame].rect, rect);
+        assert_eq!(state.node_positions[a], Pos2::new(20.0, 40.0));
+    }
+}
@@ -0,0 +1,846 @@
+use std::collections::VecDeque;
+
+use egui::{pos2, vec2, Pos2, Rect, Vec2};
+
+use super::*;
+
+/// The settings of the layered layout, which arranges nodes in columns from
+/// left to right so that connections flow in the same direction, reordering
+/// the nodes of each column to reduce the crossings between connections.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayeredLayout {
+    /// The horizontal space between two columns of nodes, in graph units.
+    pub layer_spacing: f32,
+    /// The vertical space between two nodes of the same column.
+    pub node_spacing: f32,
+    /// How many times the order of the columns is refined to reduce the
+    /// crossings, alternating between left-to-right and right-to-left passes.
+    pub sweeps: usize,
+    /// The size assumed for the nodes that haven't been drawn yet.
+    pub default_node_size: Vec2,
+}
+
+impl Default for LayeredLayout {
+    fn default() -> Self {
+        Self {
+            layer_spacing: 80.0,
+            node_spacing: 30.0,
+            sweeps: 8,
+            default_node_size: vec2(200.0, 100.0),
+        }
+    }
+}
+
+impl LayeredLayout {
+    /// Computes the position of each of `nodes`, with the layout starting at
+    /// the origin. Only the connections between `nodes` are taken into
+    /// account. Connections going back to a previous column, which only exist
+    /// in graphs with cycles, are ignored.
+    ///
+    /// The size of each node is taken from `node_rects`, which is typically
+    /// [`GraphEditorState::node_rects`].
+    pub fn arrange<NodeData, DataType, ValueType>(
+        &self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        nodes: &[NodeId],
+        node_rects: &NodeRects,
+    ) -> SecondaryMap<NodeId, Pos2> {
+        let mut index = SecondaryMap::<NodeId, usize>::new();
+        let mut vertices = vec![];
+        for node_id in nodes.iter().copied() {
+            if graph.nodes.contains_key(node_id) && !index.contains_key(node_id) {
+                index.insert(node_id, vertices.len());
+                vertices.push(node_id);
+            }
+        }
+        let node_count = vertices.len();
+
+        let mut edges: Vec<(usize, usize)> = graph
+            .iter_connections()
+            .filter_map(|(input, output)| {
+                let from = *index.get(graph[output].node)?;
+                let to = *index.get(graph[input].node)?;
+                (from != to).then_some((from, to))
+            })
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+
+        // Each node goes one column after the last of its predecessors. When
+        // the nodes left are all part of a cycle, the one with the fewest
+        // predecessors left is placed anyway, breaking the cycle.
+        let mut successors = vec![SVec::<usize>::new(); node_count];
+        let mut in_degree = vec![0; node_count];
+        for (from, to) in edges.iter().copied() {
+            successors[from].push(to);
+            in_degree[to] += 1;
+        }
+        let mut layer_of = vec![0; node_count];
+        let mut placed = vec![false; node_count];
+        let mut ready: VecDeque<usize> = (0..node_count)
+            .filter(|vertex| in_degree[*vertex] == 0)
+            .collect();
+        for _ in 0..node_count {
+            let vertex = ready.pop_front().unwrap_or_else(|| {
+                (0..node_count)
+                    .filter(|vertex| !placed[*vertex])
+                    .min_by_key(|vertex| in_degree[*vertex])
+                    .expect("Some nodes are left to place")
+            });
+            placed[vertex] = true;
+            for next in successors[vertex].iter().copied() {
+                if !placed[next] {
+                    layer_of[next] = layer_of[next].max(layer_of[vertex] + 1);
+                    in_degree[next] -= 1;
+                    if in_degree[next] == 0 {
+                        ready.push_back(next);
+                    }
+                }
+            }
+        }
+
+        // Connections spanning several columns go through a dummy vertex in
+        // each column they cross, so that they take part in the ordering.
+        let layer_count = layer_of.iter().max().map(|last| last + 1).unwrap_or(0);
+        let mut layers = vec![vec![]; layer_count];
+        for (vertex, layer) in layer_of.iter().enumerate() {
+            layers[*layer].push(vertex);
+        }
+        let mut upper = vec![SVec::<usize>::new(); node_count];
+        let mut lower = vec![SVec::<usize>::new(); node_count];
+        for (from, to) in edges.iter().copied() {
+            if layer_of[from] >= layer_of[to] {
+                continue;
+            }
+            let mut previous = from;
+            for column in &mut layers[layer_of[from] + 1..layer_of[to]] {
+                let dummy = upper.len();
+                upper.push(SVec::from_slice(&[previous]));
+                lower.push(SVec::new());
+                lower[previous].push(dummy);
+                column.push(dummy);
+                previous = dummy;
+            }
+            lower[previous].push(to);
+            upper[to].push(previous);
+        }
+
+        // Barycenter heuristic: each vertex moves to the average rank of its
+        // neighbours in the column that was just ordered.
+        let mut rank = vec![0; upper.len()];
+        for layer in &layers {
+            update_ranks(layer, &mut rank);
+        }
+        let mut best_layers = layers.clone();
+        let mut best_crossings = count_crossings(&layers, &lower, &rank);
+        for sweep in 0..self.sweeps {
+            if best_crossings == 0 {
+                break;
+            }
+            let (order, neighbours): (Vec<usize>, _) = if sweep % 2 == 0 {
+                ((1..layer_count).collect(), &upper)
+            } else {
+                ((0..layer_count.saturating_sub(1)).rev().collect(), &lower)
+            };
+            for layer in order {
+                let barycenter = |vertex: usize| {
+                    let adjacent = &neighbours[vertex];
+                    if adjacent.is_empty() {
+                        rank[vertex] as f32
+                    } else {
+                        adjacent
+                            .iter()
+                            .map(|other| rank[*other] as f32)
+                            .sum::<f32>()
+                            / adjacent.len() as f32
+                    }
+                };
+                let mut keyed: Vec<(f32, usize)> = layers[layer]
+                    .iter()
+                    .map(|vertex| (barycenter(*vertex), *vertex))
+                    .collect();
+                keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                layers[layer] = keyed.into_iter().map(|(_, vertex)| vertex).collect();
+                update_ranks(&layers[layer], &mut rank);
+            }
+            let crossings = count_crossings(&layers, &lower, &rank);
+            if crossings < best_crossings {
+                best_layers = layers.clone();
+                best_crossings = crossings;
+            }
+        }
+
+        // Columns are centered vertically. Dummy vertices take no space, but
+        // get some spacing so that connections pass between the nodes.
+        let size = |vertex: usize| {
+            if vertex < node_count {
+                node_rects
+                    .get(&vertices[vertex])
+                    .map(|rect| rect.size())
+                    .unwrap_or(self.default_node_size)
+            } else {
+                Vec2::ZERO
+            }
+        };
+        let column_height = |layer: &[usize]| {
+            layer.iter().map(|vertex| size(*vertex).y).sum::<f32>()
+                + self.node_spacing * layer.len().saturating_sub(1) as f32
+        };
+        let max_height = best_layers
+            .iter()
+            .map(|layer| column_height(layer))
+            .fold(0.0, f32::max);
+        let mut positions = SecondaryMap::new();
+        let mut x = 0.0;
+        for layer in &best_layers {
+            let mut y = (max_height - column_height(layer)) / 2.0;
+            let mut width: f32 = 0.0;
+            for vertex in layer.iter().copied() {
+                if vertex < node_count {
+                    positions.insert(vertices[vertex], pos2(x, y));
+                }
+                width = width.max(size(vertex).x);
+                y += size(vertex).y + self.node_spacing;
+            }
+            x += width + self.layer_spacing;
+        }
+        positions
+    }
+}
+
+/// Stores the index of each vertex of `layer` in `rank`.
+fn update_ranks(layer: &[usize], rank: &mut [usize]) {
+    for (index, vertex) in layer.iter().enumerate() {
+        rank[*vertex] = index;
+    }
+}
+
+/// Counts the crossings between the connections of consecutive columns.
+fn count_crossings(layers: &[Vec<usize>], lower: &[SVec<usize>], rank: &[usize]) -> usize {
+    let mut crossings = 0;
+    for layer in layers {
+        let links: Vec<(usize, usize)> = layer
+            .iter()
+            .flat_map(|from| lower[*from].iter().map(|to| (rank[*from], rank[*to])))
+            .collect();
+        for (i, (a_from, a_to)) in links.iter().enumerate() {
+            for (b_from, b_to) in &links[i + 1..] {
+                if (a_from < b_from && a_to > b_to) || (a_from > b_from && a_to < b_to) {
+                    crossings += 1;
+                }
+            }
+        }
+    }
+    crossings
+}
+
+/// The settings of the force-directed layout, which simulates connections as
+/// springs pulling nodes together while all the nodes push each other away.
+/// Unlike [`LayeredLayout`], it doesn't assume connections flow in a single
+/// direction, which suits graphs with cycles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForceLayout {
+    /// The horizontal space connections settle at between two nodes, in
+    /// graph units.
+    pub spring_length: f32,
+    /// How strongly connections pull their nodes to `spring_length`.
+    pub spring_strength: f32,
+    /// How strongly all the nodes push each other away. The push decreases
+    /// with the square of the distance between nodes.
+    pub repulsion: f32,
+    /// The space kept between overlapping nodes once they are pushed apart.
+    pub node_spacing: f32,
+    /// The largest distance a node travels in a single step.
+    pub max_step: f32,
+    /// The size assumed for the nodes that haven't been drawn yet.
+    pub default_node_size: Vec2,
+}
+
+impl Default for ForceLayout {
+    fn default() -> Self {
+        Self {
+            spring_length: 80.0,
+            spring_strength: 0.05,
+            repulsion: 20000.0,
+            node_spacing: 20.0,
+            max_step: 20.0,
+            default_node_size: vec2(200.0, 100.0),
+        }
+    }
+}
+
+impl ForceLayout {
+    /// Runs the simulation for `iterations` steps, or until it comes to
+    /// rest. See [`ForceLayout::step`].
+    pub fn run<NodeData, DataType, ValueType>(
+        &self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        positions: &mut SecondaryMap<NodeId, Pos2>,
+        node_rects: &NodeRects,
+        pinned: &SecondaryMap<NodeId, ()>,
+        iterations: usize,
+    ) {
+        for _ in 0..iterations {
+            if self.step(graph, positions, node_rects, pinned) < REST_DISTANCE {
+                break;
+            }
+        }
+    }
+
+    /// Moves the nodes in `positions` by a single step of the simulation,
+    /// then pushes apart the nodes that overlap. The `pinned` nodes push and
+    /// pull the others, but don't move themselves. Returns the largest
+    /// distance travelled by a node.
+    ///
+    /// The size of each node is taken from `node_rects`, which is typically
+    /// [`GraphEditorState::node_rects`].
+    pub fn step<NodeData, DataType, ValueType>(
+        &self,
+        graph: &Graph<NodeData, DataType, ValueType>,
+        positions: &mut SecondaryMap<NodeId, Pos2>,
+        node_rects: &NodeRects,
+        pinned: &SecondaryMap<NodeId, ()>,
+    ) -> f32 {
+        let nodes: Vec<NodeId> = positions
+            .keys()
+            .filter(|node_id| graph.nodes.contains_key(*node_id))
+            .collect();
+        let index: SecondaryMap<NodeId, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node_id)| (*node_id, index))
+            .collect();
+        let sizes: Vec<Vec2> = nodes
+            .iter()
+            .map(|node_id| {
+                node_rects
+                    .get(node_id)
+                    .map(|rect| rect.size())
+                    .unwrap_or(self.default_node_size)
+            })
+            .collect();
+        let movable: Vec<bool> = nodes
+            .iter()
+            .map(|node_id| !pinned.contains_key(*node_id))
+            .collect();
+        let start: Vec<Pos2> = nodes
+            .iter()
+            .zip(&sizes)
+            .map(|(node_id, size)| positions[*node_id] + *size / 2.0)
+            .collect();
+        // Nodes on top of each other are pulled apart horizontally
+        let direction = |from: Pos2, to: Pos2| {
+            let delta = to - from;
+            if delta.length() > 0.0 {
+                (delta.normalized(), delta.length())
+            } else {
+                (vec2(1.0, 0.0), 0.0)
+            }
+        };
+
+        let mut forces = vec![Vec2::ZERO; nodes.len()];
+        for i in 0..nodes.len() {
+            for j in i + 1..nodes.len() {
+                let (dir, distance) = direction(start[i], start[j]);
+                let push = dir * self.repulsion / distance.max(1.0).powi(2);
+                forces[i] -= push;
+                forces[j] += push;
+            }
+        }
+        for (input, output) in graph.iter_connections() {
+            let i = match index.get(graph[output].node) {
+                Some(i) => *i,
+                None => continue,
+            };
+            let j = match index.get(graph[input].node) {
+                Some(j) => *j,
+                None => continue,
+            };
+            if i == j {
+                continue;
+            }
+            let (dir, distance) = direction(start[i], start[j]);
+            let rest = self.spring_length + (sizes[i].x + sizes[j].x) / 2.0;
+            let pull = dir * (distance - rest) * self.spring_strength;
+            forces[i] += pull;
+            forces[j] -= pull;
+        }
+
+        let mut centers = start.clone();
+        for (i, force) in forces.iter().enumerate() {
+            if movable[i] {
+                let length = force.length();
+                centers[i] += if length > self.max_step {
+                    *force * self.max_step / length
+                } else {
+                    *force
+                };
+            }
+        }
+
+        // Overlapping nodes are pushed apart along the axis where they
+        // overlap the least, which is the shortest way out.
+        for i in 0..nodes.len() {
+            for j in i + 1..nodes.len() {
+                let reach = (sizes[i] + sizes[j]) / 2.0 + Vec2::splat(self.node_spacing);
+                let (dir, _) = direction(centers[i], centers[j]);
+                let delta = centers[j] - centers[i];
+                let overlap = reach - delta.abs();
+                if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                    continue;
+                }
+                let push = if overlap.x < overlap.y {
+                    vec2(overlap.x * dir.x.signum(), 0.0)
+                } else {
+                    vec2(0.0, overlap.y * if dir.y < 0.0 { -1.0 } else { 1.0 })
+                };
+                match (movable[i], movable[j]) {
+                    (true, true) => {
+                        centers[i] -= push / 2.0;
+                        centers[j] += push / 2.0;
+                    }
+                    (true, false) => centers[i] -= push,
+                    (false, true) => centers[j] += push,
+                    (false, false) => {}
+                }
+            }
+        }
+
+        let mut largest_move: f32 = 0.0;
+        for (i, node_id) in nodes.iter().enumerate() {
+            largest_move = largest_move.max(centers[i].distance(start[i]));
+            positions[*node_id] = centers[i] - sizes[i] / 2.0;
+        }
+        largest_move
+    }
+}
+
+/// Below this distance travelled in a step, a [`ForceLayout`] is at rest.
+const REST_DISTANCE: f32 = 0.05;
+
+/// A [`ForceLayout`] animated in the editor, running a few steps every frame.
+/// Started with [`GraphEditorState::animate_force_layout`].
+#[derive(Clone, Debug)]
+pub struct LayoutAnimation {
+    pub layout: ForceLayout,
+    /// The steps simulated each time the editor is drawn.
+    pub steps_per_frame: usize,
+    /// The steps left before the animation stops, unless the nodes come to
+    /// rest earlier.
+    pub steps_left: usize,
+    /// How far each node was moved since the animation started. Recorded in
+    /// the history as a single entry when the animation stops.
+    moved: SecondaryMap<NodeId, Vec2>,
+}
+
+/// How [`GraphEditorState::align_selection`] lines up the selected nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    /// Aligns the left edges with the leftmost one.
+    Left,
+    /// Aligns the right edges with the rightmost one.
+    Right,
+    /// Aligns the top edges with the topmost one.
+    Top,
+    /// Aligns the bottom edges with the bottommost one.
+    Bottom,
+    /// Aligns the centers on the vertical line through the center of the
+    /// selection.
+    CenterX,
+    /// Aligns the centers on the horizontal line through the center of the
+    /// selection.
+    CenterY,
+}
+
+/// The axis along which [`GraphEditorState::distribute_selection`] spaces out
+/// the selected nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distribution {
+    Horizontal,
+    Vertical,
+}
+
+impl<NodeData, DataType, ValueType, NodeTemplate, UserState>
+    GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState>
+where
+    NodeData: NodeDataTrait,
+    NodeData::Response: UserResponseTrait,
+{
+    /// Arranges the nodes with a [`LayeredLayout`], using the sizes they had
+    /// the last time the editor was drawn. When `selection_only` is true, only
+    /// the selected nodes are arranged. The arranged nodes keep the top-left
+    /// corner of their bounding box.
+    ///
+    /// The move is recorded as a single entry of the history, and a `MoveNode`
+    /// is returned for every node that moved.
+    pub fn layout_layered(
+        &mut self,
+        layout: &LayeredLayout,
+        selection_only: bool,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let nodes = if selection_only {
+            self.selected_nodes.clone()
+        } else {
+            self.node_order.clone()
+        };
+        let arranged = layout.arrange(&self.graph, &nodes, &self.node_rects);
+        let origin = arranged
+            .keys()
+            .filter_map(|node_id| self.node_positions.get(node_id))
+            .fold(pos2(f32::INFINITY, f32::INFINITY), |origin, pos| {
+                origin.min(*pos)
+            });
+        if !origin.is_finite() {
+            return vec![];
+        }
+        let targets: Vec<(NodeId, Pos2)> = arranged
+            .into_iter()
+            .map(|(node_id, pos)| (node_id, origin + pos.to_vec2()))
+            .collect();
+        self.move_nodes_to(targets)
+    }
+
+    /// Arranges all the nodes with a [`ForceLayout`], running `iterations`
+    /// steps at once. The [`GraphEditorState::pinned_nodes`] stay in place.
+    ///
+    /// The move is recorded as a single entry of the history, and a `MoveNode`
+    /// is returned for every node that moved.
+    pub fn layout_force_directed(
+        &mut self,
+        layout: &ForceLayout,
+        iterations: usize,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut positions = self.node_positions.clone();
+        layout.run(
+            &self.graph,
+            &mut positions,
+            &self.node_rects,
+            &self.pinned_nodes,
+            iterations,
+        );
+        self.move_nodes_to(positions)
+    }
+
+    /// Starts animating a [`ForceLayout`] in the editor, running
+    /// `steps_per_frame` steps every frame for at most `iterations` steps. An
+    /// animation already running is stopped first. The editor also stops the
+    /// animation when the user presses a button or a key over it, so that the
+    /// moves are always undone after the edits made next.
+    pub fn animate_force_layout(
+        &mut self,
+        layout: ForceLayout,
+        steps_per_frame: usize,
+        iterations: usize,
+    ) {
+        self.stop_layout_animation();
+        self.history.seal();
+        self.layout_animation = Some(LayoutAnimation {
+            layout,
+            steps_per_frame,
+            steps_left: iterations,
+            moved: SecondaryMap::new(),
+        });
+    }
+
+    /// Stops the running layout animation, if any, recording the moves it
+    /// made as a single entry of the history.
+    pub fn stop_layout_animation(&mut self) {
+        if let Some(animation) = self.layout_animation.take() {
+            self.history.seal();
+            for (node, delta) in animation.moved {
+                if self.node_positions.contains_key(node) && delta != Vec2::ZERO {
+                    self.history.push(EditorCommand::MoveNodes {
+                        moves: vec![(node, delta)],
+                    });
+                }
+            }
+            self.history.seal();
+        }
+    }
+
+    /// Runs the steps of the layout animation for this frame. Returns a
+    /// `MoveNode` for every node that moved.
+    pub(crate) fn step_layout_animation(
+        &mut self,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let animation = match self.layout_animation.as_mut() {
+            Some(animation) => animation,
+            None => return vec![],
+        };
+        let mut positions = self.node_positions.clone();
+        let mut at_rest = false;
+        for _ in 0..animation.steps_per_frame.min(animation.steps_left) {
+            animation.steps_left -= 1;
+            let largest_move = animation.layout.step(
+                &self.graph,
+                &mut positions,
+                &self.node_rects,
+                &self.pinned_nodes,
+            );
+            if largest_move < REST_DISTANCE {
+                at_rest = true;
+                break;
+            }
+        }
+
+        let mut responses = vec![];
+        for (node, position) in positions {
+            let drag_delta = position - self.node_positions[node];
+            if drag_delta != Vec2::ZERO {
+                self.node_positions[node] = position;
+                *animation
+                    .moved
+                    .entry(node)
+                    .expect("Node should exist")
+                    .or_default() += drag_delta;
+                responses.push(NodeResponse::MoveNode { node, drag_delta });
+            }
+        }
+        if at_rest || animation.steps_left == 0 {
+            self.stop_layout_animation();
+        }
+        responses
+    }
+
+    /// Lines up the selected nodes, using their rects from the last time the
+    /// editor was drawn. Returns a `MoveNode` for every node that moved, and
+    /// records the move as a single entry of the history.
+    pub fn align_selection(
+        &mut self,
+        alignment: Alignment,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let rects = self.selected_rects();
+        let bounds = rects
+            .iter()
+            .fold(Rect::NOTHING, |bounds, (_, rect)| bounds.union(*rect));
+        let targets: Vec<(NodeId, Pos2)> = rects
+            .iter()
+            .map(|(node_id, rect)| {
+                let delta = match alignment {
+                    Alignment::Left => vec2(bounds.left() - rect.left(), 0.0),
+                    Alignment::Right => vec2(bounds.right() - rect.right(), 0.0),
+                    Alignment::Top => vec2(0.0, bounds.top() - rect.top()),
+                    Alignment::Bottom => vec2(0.0, bounds.bottom() - rect.bottom()),
+                    Alignment::CenterX => vec2(bounds.center().x - rect.center().x, 0.0),
+                    Alignment::CenterY => vec2(0.0, bounds.center().y - rect.center().y),
+                };
+                (*node_id, self.node_positions[*node_id] + delta)
+            })
+            .collect();
+        self.move_nodes_to(targets)
+    }
+
+    /// Spaces out the selected nodes so that the gaps between them are all
+    /// the same along `distribution`, using their rects from the last time
+    /// the editor was drawn. The first and last nodes stay in place, so this
+    /// needs at least three nodes. Returns a `MoveNode` for every node that
+    /// moved, and records the move as a single entry of the history.
+    pub fn distribute_selection(
+        &mut self,
+        distribution: Distribution,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let axis = |vec: Vec2| match distribution {
+            Distribution::Horizontal => vec.x,
+            Distribution::Vertical => vec.y,
+        };
+        let mut rects = self.selected_rects();
+        if rects.len() < 3 {
+            return vec![];
+        }
+        rects.sort_by(|(_, a), (_, b)| {
+            axis(a.center().to_vec2()).total_cmp(&axis(b.center().to_vec2()))
+        });
+
+        let start = axis(rects[0].1.min.to_vec2());
+        let end = axis(rects[rects.len() - 1].1.max.to_vec2());
+        let occupied: f32 = rects.iter().map(|(_, rect)| axis(rect.size())).sum();
+        let gap = (end - start - occupied) / (rects.len() - 1) as f32;
+
+        let mut cursor = start;
+        let mut targets = vec![];
+        for (node_id, rect) in rects {
+            let offset = cursor - axis(rect.min.to_vec2());
+            let delta = match distribution {
+                Distribution::Horizontal => vec2(offset, 0.0),
+                Distribution::Vertical => vec2(0.0, offset),
+            };
+            targets.push((node_id, self.node_positions[node_id] + delta));
+            cursor += axis(rect.size()) + gap;
+        }
+        self.move_nodes_to(targets)
+    }
+
+    /// Returns the rects of the selected nodes that were drawn.
+    fn selected_rects(&self) -> Vec<(NodeId, Rect)> {
+        self.selected_nodes
+            .iter()
+            .filter(|node_id| self.node_positions.contains_key(**node_id))
+            .filter_map(|node_id| Some((*node_id, *self.node_rects.get(node_id)?)))
+            .collect()
+    }
+
+    /// Moves nodes to the given positions, recording it as a single entry of
+    /// the history. Returns a `MoveNode` for every node that moved.
+    pub fn move_nodes_to(
+        &mut self,
+        positions: impl IntoIterator<Item = (NodeId, Pos2)>,
+    ) -> Vec<NodeResponse<NodeData::Response, NodeData>> {
+        let mut responses = vec![];
+        self.history.seal();
+        for (node, position) in positions {
+            let current = match self.node_positions.get_mut(node) {
+                Some(current) => current,
+                None => continue,
+            };
+            let drag_delta = position - *current;
+            if drag_delta == Vec2::ZERO {
+                continue;
+            }
+            *current = position;
+            self.history.push(EditorCommand::MoveNodes {
+                moves: vec![(node, drag_delta)],
+            });
+            responses.push(NodeResponse::MoveNode { node, drag_delta });
+        }
+        self.history.seal();
+        responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::{create_node, TestState};
+
+    #[test]
+    pub fn test_layered_layout() {
+        let mut state = TestState::default();
+        let (a, _, a_out) = create_node(&mut state, pos2(500.0, 500.0));
+        let (b, b_in, b_out) = create_node(&mut state, pos2(100.0, 100.0));
+        let (c, c_in, _) = create_node(&mut state, pos2(0.0, 300.0));
+        let (d, d_in, d_out) = create_node(&mut state, pos2(300.0, 0.0));
+        state.graph.add_connection(a_out, b_in);
+        state.graph.add_connection(b_out, c_in);
+        state.graph.add_connection(a_out, d_in);
+        state.graph.add_connection(d_out, c_in);
+
+        let responses = state.layout_layered(&LayeredLayout::default(), false);
+        assert_eq!(responses.len(), 4);
+        let pos = |node| state.node_positions[node];
+        assert!(pos(a).x < pos(b).x && pos(b).x < pos(c).x);
+        assert_eq!(pos(b).x, pos(d).x);
+        assert!((pos(b).y - pos(d).y).abs() >= 100.0);
+        // The nodes started with a bounding box at the origin
+        assert_eq!(pos(a).x, 0.0);
+        assert_eq!(pos(b).y.min(pos(d).y), 0.0);
+
+        // The whole layout is undone at once
+        state.undo();
+        assert_eq!(state.node_positions[a], pos2(500.0, 500.0));
+        assert_eq!(state.node_positions[c], pos2(0.0, 300.0));
+    }
+
+    #[test]
+    pub fn test_layered_layout_crossings() {
+        let mut state = TestState::default();
+        let (a1, _, a1_out) = create_node(&mut state, Pos2::ZERO);
+        let (a2, _, a2_out) = create_node(&mut state, Pos2::ZERO);
+        let (b1, b1_in, b1_out) = create_node(&mut state, Pos2::ZERO);
+        let (b2, b2_in, b2_out) = create_node(&mut state, Pos2::ZERO);
+        state.graph.add_connection(a1_out, b2_in);
+        state.graph.add_connection(a2_out, b1_in);
+
+        let positions =
+            LayeredLayout::default().arrange(&state.graph, &state.node_order, &NodeRects::new());
+        let above = |x, y| positions[x].y < positions[y].y;
+        assert_eq!(above(a1, a2), above(b2, b1));
+
+        // A cycle is broken rather than looping forever
+        let a1_in = state.graph[a1].get_input("in").unwrap();
+        state.graph.add_connection(b2_out, a1_in);
+        state.graph.add_connection(b1_out, a1_in);
+        let positions =
+            LayeredLayout::default().arrange(&state.graph, &state.node_order, &NodeRects::new());
+        assert_eq!(positions.len(), 4);
+    }
+
+    #[test]
+    pub fn test_force_layout() {
+        let mut state = TestState::default();
+        let (a, _, a_out) = create_node(&mut state, pos2(0.0, 0.0));
+        let (b, b_in, _) = create_node(&mut state, pos2(2000.0, 0.0));
+        let (c, _, _) = create_node(&mut state, pos2(10.0, 10.0));
+        state.graph.add_connection(a_out, b_in);
+        state.pinned_nodes.insert(a, ());
+
+        let responses = state.layout_force_directed(&ForceLayout::default(), 500);
+        assert!(!responses.is_empty());
+        let pos = |node| state.node_positions[node];
+        assert_eq!(pos(a), pos2(0.0, 0.0));
+        // The connection pulled `b` closer, and `c` was pushed off `a`
+        assert!(pos(b).x < 1000.0);
+        let rect = |node| egui::Rect::from_min_size(pos(node), vec2(200.0, 100.0));
+        assert!(!rect(a).intersects(rect(c)));
+        assert!(!rect(b).intersects(rect(c)));
+
+        // The animation moves the nodes over several frames, and is undone
+        // at once
+        state.undo();
+        assert_eq!(state.node_positions[b], pos2(2000.0, 0.0));
+        state.animate_force_layout(ForceLayout::default(), 5, 20);
+        let mut frames = 0;
+        while state.layout_animation.is_some() {
+            assert!(!state.step_layout_animation().is_empty());
+            frames += 1;
+        }
+        assert_eq!(frames, 4);
+        assert!(state.node_positions[b].x < 2000.0);
+        state.undo();
+        assert_eq!(state.node_positions[b], pos2(2000.0, 0.0));
+        assert_eq!(state.node_positions[c], pos2(10.0, 10.0));
+
+        // Undoing during the animation stops it, and undoes its moves before
+        // the edits made earlier
+        state.animate_force_layout(ForceLayout::default(), 5, 20);
+        state.step_layout_animation();
+        state.undo();
+        assert!(state.layout_animation.is_none());
+        assert_eq!(state.node_positions[b], pos2(2000.0, 0.0));
+        assert!(state.graph.nodes.contains_key(c));
+    }
+
+    #[test]
+    pub fn test_align_and_distribute() {
+        let mut state = TestState::default();
+        let mut nodes = vec![];
+        for (x, y, width) in [(0.0, 0.0, 100.0), (300.0, 40.0, 50.0), (120.0, 90.0, 80.0)] {
+            let (node, _, _) = create_node(&mut state, pos2(x, y));
+            // Rects are a bit off the positions, like the frame of a node
+            let rect = Rect::from_min_size(pos2(x - 5.0, y - 5.0), vec2(width, 60.0));
+            state.node_rects.insert(node, rect);
+            nodes.push(node);
+        }
+        state.selected_nodes = nodes.clone();
+
+        let responses = state.align_selection(Alignment::Left);
+        assert_eq!(responses.len(), 2);
+        for node in &nodes {
+            assert_eq!(state.node_positions[*node].x, 0.0);
+        }
+        state.undo();
+        assert_eq!(state.node_positions[nodes[1]].x, 300.0);
+
+        state.align_selection(Alignment::Bottom);
+        for node in &nodes {
+            assert_eq!(state.node_positions[*node].y, 90.0);
+        }
+        state.undo();
+
+        // The outer nodes span 350 units, leaving 120 for the 2 gaps
+        let responses = state.distribute_selection(Distribution::Horizontal);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(state.node_positions[nodes[2]].x, 160.0);
+        assert_eq!(state.node_positions[nodes[0]].x, 0.0);
+        assert_eq!(state.node_positions[nodes[1]].x, 300.0);
+    }
+}
@@ -19,6 +19,14 @@ pub mod index_impls;
 /// Implementing the main methods for the `Graph`
 pub mod graph_impls;
 
+/// Traversal utilities for the `Graph`: topological sorting, cycle detection
+/// and upstream / downstream queries
+pub mod graph_traversal;
+
+/// A generic engine to evaluate graphs, caching the values of outputs
+pub mod evaluation;
+pub use evaluation::*;
+
 /// Custom error types, crate-wide
 pub mod error;
 pub use error::*;
@@ -28,6 +36,30 @@ pub use error::*;
 pub mod ui_state;
 pub use ui_state::*;
 
+/// Undo / redo history of the edits made in the graph editor
+pub mod history;
+pub use history::*;
+
+/// Copy and paste of nodes, detached from the graph they come from
+pub mod clipboard;
+pub use clipboard::*;
+
+/// Reroute knots, changing the path along which connections are drawn
+pub mod reroute;
+pub use reroute::*;
+
+/// Comment frames, grouping nodes visually behind them
+pub mod comment_frame;
+pub use comment_frame::*;
+
+/// The colours and metrics of the graph editor
+pub mod style;
+pub use style::*;
+
+/// Automatic arrangement of the nodes of a graph
+pub mod layout;
+pub use layout::*;
+
 /// The node finder is a tiny widget allowing to create new node types
 pub mod node_finder;
 pub use node_finder::*;
@@ -43,4 +75,10 @@ pub use traits::*;
 
 mod utils;
 
+mod curves;
+
+mod minimap;
+
+mod scale;
+
 mod color_hex_utils;
@@ -0,0 +1,97 @@
+use egui::{Pos2, Rect, Vec2};
+
+/// The space left around the nodes in the minimap, as a fraction of their
+/// bounding box.
+const BOUNDS_MARGIN: f32 = 0.1;
+
+/// Maps graph space onto the minimap, fitting `bounds` inside of it while
+/// keeping its aspect ratio.
+#[derive(Clone, Copy, Debug)]
+pub struct MinimapTransform {
+    /// The graph point drawn at the center of the minimap.
+    center: Pos2,
+    /// The minimap, in screen space.
+    rect: Rect,
+    /// The size of a graph unit on the minimap.
+    scale: f32,
+}
+
+impl MinimapTransform {
+    pub fn new(rect: Rect, bounds: Rect) -> Self {
+        let bounds = bounds.expand2(bounds.size() * BOUNDS_MARGIN);
+        let scale = (rect.width() / bounds.width()).min(rect.height() / bounds.height());
+        Self {
+            center: bounds.center(),
+            rect,
+            scale: if scale.is_finite() { scale } else { 1.0 },
+        }
+    }
+
+    /// Converts a position in graph space into a position on the minimap.
+    pub fn graph_to_minimap(&self, pos: Pos2) -> Pos2 {
+        self.rect.center() + (pos - self.center) * self.scale
+    }
+
+    /// Converts a rect in graph space into a rect on the minimap.
+    pub fn rect_to_minimap(&self, rect: Rect) -> Rect {
+        Rect::from_min_max(
+            self.graph_to_minimap(rect.min),
+            self.graph_to_minimap(rect.max),
+        )
+    }
+
+    /// Converts a position on the minimap into a position in graph space.
+    pub fn minimap_to_graph(&self, pos: Pos2) -> Pos2 {
+        self.center + (pos - self.rect.center()) / self.scale
+    }
+}
+
+/// Returns the bounding box of `rects` and of the `viewport`, so that the
+/// viewport stays on the minimap when it is scrolled away from the nodes.
+pub fn bounds(rects: impl Iterator<Item = Rect>, viewport: Rect) -> Rect {
+    let bounds = rects.fold(viewport, |bounds, rect| bounds.union(rect));
+    if bounds.is_positive() {
+        bounds
+    } else {
+        // A single node without area and an empty viewport
+        bounds.expand2(Vec2::splat(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{pos2, vec2};
+
+    #[test]
+    pub fn test_minimap_transform() {
+        let minimap = Rect::from_min_size(pos2(500.0, 400.0), vec2(200.0, 100.0));
+        let nodes = [
+            Rect::from_min_size(pos2(0.0, 0.0), vec2(100.0, 50.0)),
+            Rect::from_min_size(pos2(900.0, 150.0), vec2(100.0, 50.0)),
+        ];
+        let viewport = Rect::from_min_size(pos2(100.0, 50.0), vec2(200.0, 100.0));
+        let transform = MinimapTransform::new(minimap, bounds(nodes.into_iter(), viewport));
+
+        // The nodes fit inside the minimap, centered
+        for node in nodes {
+            assert!(minimap.contains_rect(transform.rect_to_minimap(node)));
+        }
+        assert_eq!(
+            transform.graph_to_minimap(pos2(500.0, 100.0)),
+            minimap.center()
+        );
+        let back = transform.minimap_to_graph(transform.graph_to_minimap(pos2(123.0, 45.0)));
+        assert!(back.distance(pos2(123.0, 45.0)) < 1e-3);
+
+        // Without nodes, the viewport is shown
+        assert_eq!(bounds(std::iter::empty(), viewport), viewport);
+
+        // A viewport away from the nodes is shown along with them
+        let away = Rect::from_min_size(pos2(2000.0, -500.0), vec2(200.0, 100.0));
+        let transform = MinimapTransform::new(minimap, bounds(nodes.into_iter(), away));
+        for rect in nodes.into_iter().chain([away]) {
+            assert!(minimap.contains_rect(transform.rect_to_minimap(rect)));
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use crate::{color_hex_utils::*, NodeTemplateIter, NodeTemplateTrait};
+use crate::{NodeGraphStyle, NodeTemplateIter, NodeTemplateTrait};
 
 use egui::*;
 
@@ -35,17 +35,10 @@ where
         ui: &mut Ui,
         all_kinds: impl NodeTemplateIter<Item = NodeTemplate>,
         user_state: &mut UserState,
+        style: &NodeGraphStyle,
     ) -> Option<NodeTemplate> {
-        let background_color;
-        let text_color;
-
-        if ui.visuals().dark_mode {
-            background_color = color_from_hex("#3f3f3f").unwrap();
-            text_color = color_from_hex("#fefefe").unwrap();
-        } else {
-            background_color = color_from_hex("#fefefe").unwrap();
-            text_color = color_from_hex("#3f3f3f").unwrap();
-        }
+        let background_color = style.finder_background;
+        let text_color = style.finder_text;
 
         ui.visuals_mut().widgets.noninteractive.fg_stroke = Stroke::new(2.0, text_color);
 
@@ -0,0 +1,145 @@
+use egui::Pos2;
+
+use super::*;
+
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+
+/// The reroute knots of the connections of a graph. A connection with knots is
+/// drawn as a chain of curves going through each of them, in order. Knots are
+/// only a matter of presentation: the connections of the graph, and thus its
+/// evaluation, are left untouched.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct RerouteKnots {
+    /// The positions of the knots of each connection, in graph space, from
+    /// the output to the input.
+    knots: SecondaryMap<InputId, Vec<(OutputId, Vec<Pos2>)>>,
+}
+
+impl RerouteKnots {
+    /// Returns the knots of the connection from `output` to `input`.
+    pub fn get(&self, input: InputId, output: OutputId) -> &[Pos2] {
+        self.knots
+            .get(input)
+            .and_then(|connections| connections.iter().find(|(id, _)| *id == output))
+            .map(|(_, knots)| knots.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Returns the knots of the connection from `output` to `input`, to move
+    /// them around.
+    pub fn get_mut(&mut self, input: InputId, output: OutputId) -> Option<&mut [Pos2]> {
+        self.knots
+            .get_mut(input)?
+            .iter_mut()
+            .find(|(id, _)| *id == output)
+            .map(|(_, knots)| knots.as_mut_slice())
+    }
+
+    /// Adds a knot at `position` to the connection from `output` to `input`.
+    /// It is placed at `index` among the existing knots, clamped to the valid
+    /// range.
+    pub fn insert(&mut self, input: InputId, output: OutputId, index: usize, position: Pos2) {
+        let connections = match self.knots.entry(input) {
+            Some(entry) => entry.or_default(),
+            None => return,
+        };
+        let knots = match connections.iter().position(|(id, _)| *id == output) {
+            Some(found) => &mut connections[found].1,
+            None => {
+                connections.push((output, vec![]));
+                &mut connections.last_mut().expect("Just pushed").1
+            }
+        };
+        knots.insert(index.min(knots.len()), position);
+    }
+
+    /// Replaces the knots of the connection from `output` to `input`.
+    pub fn set(&mut self, input: InputId, output: OutputId, knots: Vec<Pos2>) {
+        let connections = match self.knots.entry(input) {
+            Some(entry) => entry.or_default(),
+            None => return,
+        };
+        let found = connections.iter().position(|(id, _)| *id == output);
+        match (found, knots.is_empty()) {
+            (Some(found), true) => {
+                connections.swap_remove(found);
+            }
+            (Some(found), false) => connections[found].1 = knots,
+            (None, true) => {}
+            (None, false) => connections.push((output, knots)),
+        }
+        if connections.is_empty() {
+            self.knots.remove(input);
+        }
+    }
+
+    /// Removes the knot at `index` from the connection from `output` to
+    /// `input`, returning its position.
+    pub fn remove(&mut self, input: InputId, output: OutputId, index: usize) -> Option<Pos2> {
+        let connections = self.knots.get_mut(input)?;
+        let found = connections.iter().position(|(id, _)| *id == output)?;
+        let knots = &mut connections[found].1;
+        if index >= knots.len() {
+            return None;
+        }
+        let position = knots.remove(index);
+        if knots.is_empty() {
+            connections.swap_remove(found);
+        }
+        Some(position)
+    }
+
+    /// Only keeps the knots of the connections for which `keep` returns true.
+    /// The editor calls it with the connections of the graph, so that knots
+    /// don't outlive their connection.
+    pub fn retain(&mut self, mut keep: impl FnMut(InputId, OutputId) -> bool) {
+        self.knots.retain(|input, connections| {
+            connections.retain(|(output, _)| keep(input, *output));
+            !connections.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_reroute_knots() {
+        let mut graph = Graph::<(), (), ()>::new();
+        let node = graph.add_node("node".into(), (), |_, _| {});
+        let input = graph.add_input_param(
+            node,
+            "in".into(),
+            (),
+            (),
+            InputParamKind::ConnectionOnly,
+            true,
+        );
+        let output = graph.add_output_param(node, "out".into(), ());
+
+        let mut knots = RerouteKnots::default();
+        let (a, b) = (Pos2::new(1.0, 0.0), Pos2::new(2.0, 0.0));
+        knots.insert(input, output, 0, b);
+        knots.insert(input, output, 0, a);
+        knots.insert(input, output, 10, b);
+        assert_eq!(knots.get(input, output), &[a, b, b]);
+
+        knots.get_mut(input, output).unwrap()[2] = a;
+        assert_eq!(knots.remove(input, output, 2), Some(a));
+        assert_eq!(knots.remove(input, output, 2), None);
+        assert_eq!(knots.get(input, output), &[a, b]);
+
+        knots.set(input, output, vec![b]);
+        assert_eq!(knots.get(input, output), &[b]);
+        knots.set(input, output, vec![]);
+        assert!(knots.get_mut(input, output).is_none());
+        knots.set(input, output, vec![a, b]);
+
+        knots.retain(|_, _| false);
+        assert!(knots.get(input, output).is_empty());
+        assert!(knots.get_mut(input, output).is_none());
+    }
+}
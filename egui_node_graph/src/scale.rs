@@ -0,0 +1,103 @@
+use egui::epaint::Shadow;
+use egui::{style::WidgetVisuals, Rounding, Stroke, Style, Vec2};
+
+/// Types whose metrics can be scaled by a zoom factor. Used to draw the
+/// contents of nodes at the current zoom level of the graph editor.
+pub trait Scale {
+    fn scale(&mut self, amount: f32);
+
+    fn scaled(&self, amount: f32) -> Self
+    where
+        Self: Clone,
+    {
+        let mut scaled = self.clone();
+        scaled.scale(amount);
+        scaled
+    }
+}
+
+impl Scale for Vec2 {
+    fn scale(&mut self, amount: f32) {
+        self.x *= amount;
+        self.y *= amount;
+    }
+}
+
+impl Scale for egui::style::Margin {
+    fn scale(&mut self, amount: f32) {
+        self.left *= amount;
+        self.right *= amount;
+        self.top *= amount;
+        self.bottom *= amount;
+    }
+}
+
+impl Scale for Rounding {
+    fn scale(&mut self, amount: f32) {
+        self.ne *= amount;
+        self.nw *= amount;
+        self.se *= amount;
+        self.sw *= amount;
+    }
+}
+
+impl Scale for Stroke {
+    fn scale(&mut self, amount: f32) {
+        self.width *= amount;
+    }
+}
+
+impl Scale for Shadow {
+    fn scale(&mut self, amount: f32) {
+        self.extrusion *= amount;
+    }
+}
+
+impl Scale for WidgetVisuals {
+    fn scale(&mut self, amount: f32) {
+        self.bg_stroke.scale(amount);
+        self.fg_stroke.scale(amount);
+        self.rounding.scale(amount);
+        self.expansion *= amount;
+    }
+}
+
+impl Scale for Style {
+    fn scale(&mut self, amount: f32) {
+        if let Some(font_id) = &mut self.override_font_id {
+            font_id.size *= amount;
+        }
+        for font_id in self.text_styles.values_mut() {
+            font_id.size *= amount;
+        }
+
+        let spacing = &mut self.spacing;
+        spacing.item_spacing.scale(amount);
+        spacing.window_margin.scale(amount);
+        spacing.button_padding.scale(amount);
+        spacing.indent *= amount;
+        spacing.interact_size.scale(amount);
+        spacing.slider_width *= amount;
+        spacing.text_edit_width *= amount;
+        spacing.icon_width *= amount;
+        spacing.icon_width_inner *= amount;
+        spacing.icon_spacing *= amount;
+        spacing.tooltip_width *= amount;
+        spacing.combo_height *= amount;
+        spacing.scroll_bar_width *= amount;
+
+        let visuals = &mut self.visuals;
+        visuals.widgets.noninteractive.scale(amount);
+        visuals.widgets.inactive.scale(amount);
+        visuals.widgets.hovered.scale(amount);
+        visuals.widgets.active.scale(amount);
+        visuals.widgets.open.scale(amount);
+        visuals.selection.stroke.scale(amount);
+        visuals.window_rounding.scale(amount);
+        visuals.window_shadow.scale(amount);
+        visuals.popup_shadow.scale(amount);
+        visuals.resize_corner_size *= amount;
+        visuals.text_cursor_width *= amount;
+        visuals.clip_rect_margin *= amount;
+    }
+}
@@ -0,0 +1,183 @@
+use egui::{Color32, Visuals};
+
+use crate::color_hex_utils::color_from_hex;
+
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+
+/// The colours and metrics used to draw the graph editor. Metrics are given
+/// in graph units, so they scale with the zoom level.
+///
+/// Set [`GraphEditorState::style`](crate::GraphEditorState::style) to use a
+/// custom style. Otherwise, the editor follows egui's dark mode setting with
+/// the [`NodeGraphStyle::dark`] and [`NodeGraphStyle::light`] presets.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct NodeGraphStyle {
+    /// The background of the nodes.
+    pub node_background: Color32,
+    /// The colour of the text in the nodes.
+    pub node_text: Color32,
+    /// The title bar of the nodes is tinted by multiplying `node_background`
+    /// by this factor, unless the node has its own
+    /// [`titlebar_color`](crate::NodeDataTrait::titlebar_color).
+    pub titlebar_tint: f32,
+    /// The outline drawn around the selected nodes and comment frames.
+    pub selection_outline: Color32,
+    /// The buttons in the title bar of the nodes.
+    pub button: Color32,
+    pub button_hovered: Color32,
+    pub button_clicked: Color32,
+    /// The evaluation states shown in the title bar of the nodes.
+    pub evaluation_computing: Color32,
+    pub evaluation_done: Color32,
+    pub evaluation_error: Color32,
+    pub port_radius: f32,
+    /// The colour of the ports close enough to the cursor to be connected.
+    pub port_hovered: Color32,
+    pub wire_width: f32,
+    /// The colour of an ongoing connection that would be refused if released.
+    pub wire_rejected: Color32,
+    /// The outline drawn around the selected connections.
+    pub wire_selected_outline: Color32,
+    /// The connection under the cursor is lightened by this factor.
+    pub wire_hovered_tint: f32,
+    /// The line drawn while cutting connections with the knife.
+    pub knife: Color32,
+    pub box_selection_fill: Color32,
+    pub box_selection_stroke: Color32,
+    /// The text in the title bar of comment frames.
+    pub frame_title_text: Color32,
+    /// The colour given to new comment frames.
+    #[cfg_attr(feature = "persistence", serde(default = "frame_default"))]
+    pub frame: Color32,
+    pub finder_background: Color32,
+    pub finder_text: Color32,
+    /// The lines of the background grid, when it is shown.
+    #[cfg_attr(feature = "persistence", serde(default = "grid_default"))]
+    pub grid: Color32,
+    #[cfg_attr(feature = "persistence", serde(default = "minimap_background_default"))]
+    pub minimap_background: Color32,
+    /// The outline showing the visible part of the graph on the minimap.
+    #[cfg_attr(feature = "persistence", serde(default = "minimap_viewport_default"))]
+    pub minimap_viewport: Color32,
+}
+
+#[cfg(feature = "persistence")]
+fn frame_default() -> Color32 {
+    NodeGraphStyle::common().frame
+}
+
+#[cfg(feature = "persistence")]
+fn grid_default() -> Color32 {
+    NodeGraphStyle::common().grid
+}
+
+#[cfg(feature = "persistence")]
+fn minimap_background_default() -> Color32 {
+    NodeGraphStyle::common().minimap_background
+}
+
+#[cfg(feature = "persistence")]
+fn minimap_viewport_default() -> Color32 {
+    NodeGraphStyle::common().minimap_viewport
+}
+
+impl NodeGraphStyle {
+    pub fn dark() -> Self {
+        Self {
+            node_background: color_from_hex("#3f3f3f").unwrap(),
+            node_text: color_from_hex("#fefefe").unwrap(),
+            button: color_from_hex("#aaaaaa").unwrap(),
+            button_hovered: color_from_hex("#dddddd").unwrap(),
+            button_clicked: color_from_hex("#ffffff").unwrap(),
+            finder_background: color_from_hex("#3f3f3f").unwrap(),
+            finder_text: color_from_hex("#fefefe").unwrap(),
+            ..Self::common()
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            node_background: color_from_hex("#ffffff").unwrap(),
+            node_text: color_from_hex("#505050").unwrap(),
+            button: color_from_hex("#555555").unwrap(),
+            button_hovered: color_from_hex("#222222").unwrap(),
+            button_clicked: color_from_hex("#000000").unwrap(),
+            finder_background: color_from_hex("#fefefe").unwrap(),
+            finder_text: color_from_hex("#3f3f3f").unwrap(),
+            ..Self::common()
+        }
+    }
+
+    /// Returns the preset matching the dark mode setting of `visuals`.
+    pub fn from_visuals(visuals: &Visuals) -> Self {
+        if visuals.dark_mode {
+            Self::dark()
+        } else {
+            Self::light()
+        }
+    }
+
+    /// The values shared by both presets.
+    fn common() -> Self {
+        Self {
+            node_background: Color32::WHITE,
+            node_text: Color32::BLACK,
+            titlebar_tint: 0.8,
+            selection_outline: Color32::from_gray(204),
+            button: Color32::GRAY,
+            button_hovered: Color32::GRAY,
+            button_clicked: Color32::GRAY,
+            evaluation_computing: color_from_hex("#e6b422").unwrap(),
+            evaluation_done: color_from_hex("#4caf50").unwrap(),
+            evaluation_error: color_from_hex("#e53935").unwrap(),
+            port_radius: 5.0,
+            port_hovered: Color32::WHITE,
+            wire_width: 5.0,
+            wire_rejected: Color32::from_rgb(220, 50, 50),
+            wire_selected_outline: Color32::from_rgba_premultiplied(200, 200, 200, 200),
+            wire_hovered_tint: 1.5,
+            knife: Color32::from_rgb(220, 50, 50),
+            box_selection_fill: Color32::from_rgba_unmultiplied(200, 200, 200, 20),
+            box_selection_stroke: Color32::from_rgba_unmultiplied(200, 200, 200, 180),
+            frame_title_text: Color32::WHITE,
+            frame: Color32::from_rgb(70, 100, 140),
+            finder_background: Color32::WHITE,
+            finder_text: Color32::BLACK,
+            grid: Color32::from_rgba_unmultiplied(128, 128, 128, 40),
+            minimap_background: Color32::from_rgba_unmultiplied(30, 30, 30, 200),
+            minimap_viewport: Color32::from_rgba_unmultiplied(220, 220, 220, 220),
+        }
+    }
+}
+
+impl Default for NodeGraphStyle {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_presets() {
+        assert_eq!(
+            NodeGraphStyle::from_visuals(&Visuals::light()),
+            NodeGraphStyle::light()
+        );
+        assert_ne!(NodeGraphStyle::dark(), NodeGraphStyle::light());
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    pub fn test_style_serialization() {
+        let mut style = NodeGraphStyle::light();
+        style.wire_width = 3.0;
+        let text = ron::to_string(&style).unwrap();
+        let restored: NodeGraphStyle = ron::from_str(&text).unwrap();
+        assert_eq!(restored, style);
+    }
+}
@@ -62,7 +62,7 @@ pub trait DataTypeTrait<UserState>: PartialEq + Eq {
     ///     }
     /// }
     /// ```
-    fn name(&self) -> std::borrow::Cow<str>;
+    fn name(&self) -> std::borrow::Cow<'_, str>;
 }
 
 /// This trait must be implemented for the `NodeData` generic parameter of the
@@ -111,6 +111,12 @@ where
     ) -> bool {
         true
     }
+
+    /// The sizes this node can take. Override it for nodes with wide
+    /// widgets, such as text editors or previews.
+    fn size_constraints(&self) -> NodeSizeConstraints {
+        NodeSizeConstraints::default()
+    }
 }
 
 /// This trait can be implemented by any user type. The trait tells the library
@@ -140,7 +146,7 @@ pub trait NodeTemplateTrait: Clone {
     /// The return type is Cow<str> to allow returning owned or borrowed values
     /// more flexibly. Refer to the documentation for `DataTypeTrait::name` for
     /// more information
-    fn node_finder_label(&self, user_state: &mut Self::UserState) -> std::borrow::Cow<str>;
+    fn node_finder_label(&self, user_state: &mut Self::UserState) -> std::borrow::Cow<'_, str>;
 
     /// Returns a descriptive name for the node kind, used in the graph.
     fn node_graph_label(&self, user_state: &mut Self::UserState) -> String;
@@ -4,13 +4,36 @@ use std::marker::PhantomData;
 #[cfg(feature = "persistence")]
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Copy, Clone)]
+#[derive(Copy, Clone)]
 #[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
 pub struct PanZoom {
+    /// The offset, in screen units, of the graph origin relative to the
+    /// top-left corner of the editor.
     pub pan: egui::Vec2,
+    /// The scale factor from graph units to screen units.
     pub zoom: f32,
 }
 
+#[cfg(feature = "persistence")]
+fn reject_cycles_default() -> bool {
+    true
+}
+
+#[cfg(feature = "persistence")]
+fn zoom_min_default() -> f32 {
+    0.2
+}
+
+#[cfg(feature = "persistence")]
+fn zoom_max_default() -> f32 {
+    4.0
+}
+
+#[cfg(feature = "persistence")]
+fn grid_spacing_default() -> f32 {
+    20.0
+}
+
 #[derive(Clone)]
 #[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
 pub struct GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserState> {
@@ -21,17 +44,110 @@ pub struct GraphEditorState<NodeData, DataType, ValueType, NodeTemplate, UserSta
     /// An ongoing connection interaction: The mouse has dragged away from a
     /// port and the user is holding the click
     pub connection_in_progress: Option<(NodeId, AnyParameterId)>,
+    /// When true, the editor refuses connections that would create a cycle in
+    /// the graph. Set it to false for graphs whose semantics allow feedback.
+    #[cfg_attr(feature = "persistence", serde(default = "reject_cycles_default"))]
+    pub reject_cycles: bool,
     /// The currently selected node. Some interface actions depend on the
     /// currently selected node.
     pub selected_nodes: Vec<NodeId>,
-    /// The mouse drag start position for an ongoing box selection.
+    /// The selected connections, as input-output pairs. They are deleted
+    /// along with the selected nodes.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub selected_connections: Vec<(InputId, OutputId)>,
+    /// The selected comment frames. They are deleted along with the selected
+    /// nodes, but not the nodes inside of them.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub selected_frames: Vec<FrameId>,
+    /// The mouse drag start position for an ongoing box selection, in graph
+    /// space.
     pub ongoing_box_selection: Option<egui::Pos2>,
+    /// The start position of an ongoing knife cut, in graph space. The
+    /// connections crossed by the knife are removed when it is released.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub ongoing_knife_cut: Option<egui::Pos2>,
     /// The position of each node.
     pub node_positions: SecondaryMap<NodeId, egui::Pos2>,
+    /// The size of the nodes resized by the user, in graph space. The other
+    /// nodes fit their contents.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub node_sizes: SecondaryMap<NodeId, egui::Vec2>,
+    /// The rect of each node in graph space, as measured the last time the
+    /// editor was drawn. Used to arrange the nodes.
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub node_rects: NodeRects,
+    /// The nodes that automatic layouts leave in place.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub pinned_nodes: SecondaryMap<NodeId, ()>,
+    /// The nodes drawn collapsed, showing only their title bar.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub collapsed_nodes: SecondaryMap<NodeId, ()>,
+    /// The reroute knots of the connections. Double-clicking a connection
+    /// adds one, and double-clicking a knot removes it.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub reroute_knots: RerouteKnots,
+    /// The comment frames drawn behind the nodes. Ctrl+G creates one around
+    /// the selected nodes.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub frames: SlotMap<FrameId, CommentFrame>,
     /// The node finder is used to create new nodes.
     pub node_finder: Option<NodeFinder<NodeTemplate>>,
-    /// The panning of the graph viewport.
+    /// The panning and zoom of the graph viewport.
     pub pan_zoom: PanZoom,
+    /// The smallest zoom level reachable by zooming out.
+    #[cfg_attr(feature = "persistence", serde(default = "zoom_min_default"))]
+    pub zoom_min: f32,
+    /// The largest zoom level reachable by zooming in.
+    #[cfg_attr(feature = "persistence", serde(default = "zoom_max_default"))]
+    pub zoom_max: f32,
+    /// The force-directed layout being animated, if any. See
+    /// [`GraphEditorState::animate_force_layout`].
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub layout_animation: Option<LayoutAnimation>,
+    /// When true, a grid is drawn behind the nodes, with lines every
+    /// `grid_spacing` graph units.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub show_grid: bool,
+    /// When true, dragged nodes and the nodes created with the node finder
+    /// snap to the points of the grid, whether it is shown or not.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub snap_to_grid: bool,
+    /// The distance between the lines of the grid, in graph units.
+    #[cfg_attr(feature = "persistence", serde(default = "grid_spacing_default"))]
+    pub grid_spacing: f32,
+    /// When true, an overview of the whole graph is drawn in the bottom-right
+    /// corner of the editor. Clicking or dragging on it moves the viewport.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub show_minimap: bool,
+    /// The style of the editor. When `None`, it follows egui's dark mode
+    /// setting, using the [`NodeGraphStyle::dark`] or [`NodeGraphStyle::light`]
+    /// preset.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub style: Option<NodeGraphStyle>,
+    /// The evaluation state shown on each node, typically copied from
+    /// [`BackgroundEvaluator::node_states`] every frame. Nodes without an entry
+    /// show nothing.
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    pub node_evaluation_states: SecondaryMap<NodeId, NodeEvaluationState>,
+    /// The edits made in the editor, which can be undone and redone.
+    #[cfg_attr(
+        feature = "persistence",
+        serde(skip, default = "EditorHistory::default")
+    )]
+    pub history: EditorHistory<NodeData, DataType, ValueType>,
+    /// The nodes copied with Ctrl+C or Ctrl+X, pasted with Ctrl+V. With the
+    /// `persistence` feature, they also go through the system clipboard.
+    #[cfg_attr(feature = "persistence", serde(skip, default = "Option::default"))]
+    pub clipboard: Option<GraphClipboard<NodeData, DataType, ValueType>>,
+    /// When true, nodes duplicated with Ctrl+D keep the connections arriving
+    /// from nodes that weren't duplicated.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub duplicate_keeps_connections: bool,
+    /// When true, the selected nodes are aligned with Alt and the arrow keys,
+    /// or Alt+C and Alt+M for their centers, and distributed with Alt+H and
+    /// Alt+V.
+    #[cfg_attr(feature = "persistence", serde(default))]
+    pub alignment_shortcuts: bool,
     pub _user_state: PhantomData<fn() -> UserState>,
 }
 
@@ -56,17 +172,52 @@ impl<NodeData, DataType, ValueType, NodeKind, UserState> Default
             graph: Default::default(),
             node_order: Default::default(),
             connection_in_progress: Default::default(),
+            reject_cycles: true,
             selected_nodes: Default::default(),
+            selected_connections: Default::default(),
+            selected_frames: Default::default(),
             ongoing_box_selection: Default::default(),
+            ongoing_knife_cut: Default::default(),
             node_positions: Default::default(),
+            node_sizes: Default::default(),
+            node_rects: Default::default(),
+            pinned_nodes: Default::default(),
+            collapsed_nodes: Default::default(),
+            reroute_knots: Default::default(),
+            frames: Default::default(),
             node_finder: Default::default(),
             pan_zoom: Default::default(),
+            zoom_min: 0.2,
+            zoom_max: 4.0,
+            layout_animation: Default::default(),
+            show_grid: false,
+            snap_to_grid: false,
+            grid_spacing: 20.0,
+            show_minimap: false,
+            style: Default::default(),
+            node_evaluation_states: Default::default(),
+            history: Default::default(),
+            clipboard: Default::default(),
+            duplicate_keeps_connections: false,
+            alignment_shortcuts: false,
             _user_state: Default::default(),
         }
     }
 }
 
+impl Default for PanZoom {
+    fn default() -> Self {
+        Self {
+            pan: egui::Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
 impl PanZoom {
+    /// Changes the zoom level by `zoom_delta`, keeping the graph point under
+    /// `point` (in screen units, relative to the editor origin) fixed on
+    /// screen. The resulting zoom is clamped to `[zoom_min, zoom_max]`.
     pub fn adjust_zoom(
         &mut self,
         zoom_delta: f32,
@@ -75,9 +226,47 @@ impl PanZoom {
         zoom_max: f32,
     ) {
         let zoom_clamped = (self.zoom + zoom_delta).clamp(zoom_min, zoom_max);
-        let zoom_delta = zoom_clamped - self.zoom;
+        let graph_point = (point - self.pan) / self.zoom;
+
+        self.zoom = zoom_clamped;
+        self.pan = point - graph_point * self.zoom;
+    }
+
+    /// Converts a position in graph space into screen space. `origin` is the
+    /// top-left corner of the editor.
+    pub fn graph_to_screen(&self, origin: egui::Pos2, pos: egui::Pos2) -> egui::Pos2 {
+        origin + self.pan + pos.to_vec2() * self.zoom
+    }
+
+    /// Converts a position in screen space into graph space. `origin` is the
+    /// top-left corner of the editor.
+    pub fn screen_to_graph(&self, origin: egui::Pos2, pos: egui::Pos2) -> egui::Pos2 {
+        ((pos - origin - self.pan) / self.zoom).to_pos2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_adjust_zoom_keeps_point_fixed() {
+        let mut pan_zoom = PanZoom {
+            pan: egui::vec2(30.0, -20.0),
+            zoom: 1.0,
+        };
+        let origin = egui::pos2(10.0, 10.0);
+        let cursor = egui::pos2(200.0, 150.0);
+        let under_cursor = pan_zoom.screen_to_graph(origin, cursor);
+
+        pan_zoom.adjust_zoom(0.5, cursor - origin, 0.2, 4.0);
+        assert_eq!(pan_zoom.zoom, 1.5);
+        let after = pan_zoom.graph_to_screen(origin, under_cursor);
+        assert!(after.distance(cursor) < 1e-3);
 
-        self.zoom += zoom_delta;
-        self.pan += point * zoom_delta;
+        pan_zoom.adjust_zoom(-10.0, cursor - origin, 0.2, 4.0);
+        assert_eq!(pan_zoom.zoom, 0.2);
+        let after = pan_zoom.graph_to_screen(origin, under_cursor);
+        assert!(after.distance(cursor) < 1e-3);
     }
 }
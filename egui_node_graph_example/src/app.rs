@@ -1,4 +1,4 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::borrow::Cow;
 
 use eframe::egui::{self, DragValue, TextStyle};
 use egui_node_graph::*;
@@ -8,6 +8,7 @@ use egui_node_graph::*;
 /// The NodeData holds a custom data struct inside each node. It's useful to
 /// store additional information that doesn't live in parameters. For this
 /// example, the node data stores the template (i.e. the "type") of the node.
+#[derive(Clone)]
 #[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
 pub struct MyNodeData {
     template: MyNodeTemplate,
@@ -16,7 +17,7 @@ pub struct MyNodeData {
 /// `DataType`s are what defines the possible range of connections when
 /// attaching two ports together. The graph UI will make sure to not allow
 /// attaching incompatible datatypes.
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
 pub enum MyDataType {
     Scalar,
@@ -30,7 +31,7 @@ pub enum MyDataType {
 /// this library makes no attempt to check this consistency. For instance, it is
 /// up to the user code in this example to make sure no parameter is created
 /// with a DataType of Scalar and a ValueType of Vec2.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
 pub enum MyValueType {
     Vec2 { value: egui::Vec2 },
@@ -355,6 +356,7 @@ impl NodeDataTrait for MyNodeData {
 }
 
 type MyGraph = Graph<MyNodeData, MyDataType, MyValueType>;
+type MyEvaluationContext<'a> = EvaluationContext<'a, MyNodeData, MyDataType, MyValueType>;
 type MyEditorState =
     GraphEditorState<MyNodeData, MyDataType, MyValueType, MyNodeTemplate, MyGraphState>;
 
@@ -365,6 +367,11 @@ pub struct NodeGraphExample {
     state: MyEditorState,
 
     user_state: MyGraphState,
+
+    // The evaluator runs on a background thread, so the UI stays responsive.
+    // It caches the values of the outputs across frames, so only the nodes
+    // affected by an edit are evaluated again.
+    evaluator: BackgroundEvaluator<MyNodeData>,
 }
 
 #[cfg(feature = "persistence")]
@@ -382,6 +389,7 @@ impl NodeGraphExample {
         Self {
             state,
             user_state: MyGraphState::default(),
+            evaluator: BackgroundEvaluator::new(),
         }
     }
 }
@@ -401,12 +409,24 @@ impl eframe::App for NodeGraphExample {
                 egui::widgets::global_dark_light_mode_switch(ui);
             });
         });
+        // Receive the progress of the evaluation, and show it on the nodes
+        self.evaluator.poll();
+        if self.evaluator.is_running() {
+            ctx.request_repaint();
+        }
+        self.state.node_evaluation_states = self.evaluator.node_states().clone();
+
         let graph_response = egui::CentralPanel::default()
             .show(ctx, |ui| {
+                #[cfg(feature = "persistence")]
+                self.state.sync_system_clipboard(ui);
                 self.state
                     .draw_graph_editor(ui, AllMyNodeTemplates, &mut self.user_state)
             })
             .inner;
+        // Let the evaluator know which nodes changed during this frame
+        self.evaluator
+            .handle_responses(&self.state.graph, &graph_response.node_responses);
         for node_response in graph_response.node_responses {
             // Here, we ignore all other graph events. But you may find
             // some use for them. For example, by playing a sound when a new
@@ -421,9 +441,14 @@ impl eframe::App for NodeGraphExample {
 
         if let Some(node) = self.user_state.active_node {
             if self.state.graph.nodes.contains_key(node) {
-                let text = match evaluate_node(&self.state.graph, node, &mut HashMap::new()) {
-                    Ok(value) => format!("The result is: {:?}", value),
-                    Err(err) => format!("Execution error: {}", err),
+                let output = self.state.graph[node].get_output("out").unwrap();
+                self.evaluator.evaluate(&self.state.graph, [node]);
+                let text = if let Some(value) = self.evaluator.output_value(output) {
+                    format!("The result is: {:?}", value)
+                } else if let Some(err) = self.evaluator.last_error() {
+                    format!("Execution error: {}", err)
+                } else {
+                    "Computing...".to_string()
                 };
                 ctx.debug_painter().text(
                     egui::pos2(10.0, 35.0),
@@ -439,153 +464,73 @@ impl eframe::App for NodeGraphExample {
     }
 }
 
-type OutputsCache = HashMap<OutputId, MyValueType>;
-
-/// Recursively evaluates all dependencies of this node, then evaluates the node itself.
-pub fn evaluate_node(
-    graph: &MyGraph,
-    node_id: NodeId,
-    outputs_cache: &mut OutputsCache,
-) -> anyhow::Result<MyValueType> {
-    // To solve a similar problem as creating node types above, we define an
-    // Evaluator as a convenience. It may be overkill for this small example,
-    // but something like this makes the code much more readable when the
-    // number of nodes starts growing.
-
-    struct Evaluator<'a> {
-        graph: &'a MyGraph,
-        outputs_cache: &'a mut OutputsCache,
-        node_id: NodeId,
-    }
-    impl<'a> Evaluator<'a> {
-        fn new(graph: &'a MyGraph, outputs_cache: &'a mut OutputsCache, node_id: NodeId) -> Self {
-            Self {
-                graph,
-                outputs_cache,
-                node_id,
-            }
-        }
-        fn evaluate_input(&mut self, name: &str) -> anyhow::Result<MyValueType> {
-            // Calling `evaluate_input` recursively evaluates other nodes in the
-            // graph until the input value for a paramater has been computed.
-            evaluate_input(self.graph, self.node_id, name, self.outputs_cache)
-        }
-        fn populate_output(
-            &mut self,
-            name: &str,
-            value: MyValueType,
-        ) -> anyhow::Result<MyValueType> {
-            // After computing an output, we don't just return it, but we also
-            // populate the outputs cache with it. This ensures the evaluation
-            // only ever computes an output once.
-            //
-            // The return value of the function is the "final" output of the
-            // node, the thing we want to get from the evaluation. The example
-            // would be slightly more contrived when we had multiple output
-            // values, as we would need to choose which of the outputs is the
-            // one we want to return. Other outputs could be used as
-            // intermediate values.
-            //
-            // Note that this is just one possible semantic interpretation of
-            // the graphs, you can come up with your own evaluation semantics!
-            populate_output(self.graph, self.outputs_cache, self.node_id, name, value)
-        }
-        fn input_vector(&mut self, name: &str) -> anyhow::Result<egui::Vec2> {
-            self.evaluate_input(name)?.try_to_vec2()
-        }
-        fn input_scalar(&mut self, name: &str) -> anyhow::Result<f32> {
-            self.evaluate_input(name)?.try_to_scalar()
-        }
-        fn output_vector(&mut self, name: &str, value: egui::Vec2) -> anyhow::Result<MyValueType> {
-            self.populate_output(name, MyValueType::Vec2 { value })
-        }
-        fn output_scalar(&mut self, name: &str, value: f32) -> anyhow::Result<MyValueType> {
-            self.populate_output(name, MyValueType::Scalar { value })
-        }
-    }
+// =========== Finally, you can evaluate the graph ============
 
-    let node = &graph[node_id];
-    let mut evaluator = Evaluator::new(graph, outputs_cache, node_id);
-    match node.user_data.template {
-        MyNodeTemplate::AddScalar => {
-            let a = evaluator.input_scalar("A")?;
-            let b = evaluator.input_scalar("B")?;
-            evaluator.output_scalar("out", a + b)
-        }
-        MyNodeTemplate::SubtractScalar => {
-            let a = evaluator.input_scalar("A")?;
-            let b = evaluator.input_scalar("B")?;
-            evaluator.output_scalar("out", a - b)
-        }
-        MyNodeTemplate::VectorTimesScalar => {
-            let scalar = evaluator.input_scalar("scalar")?;
-            let vector = evaluator.input_vector("vector")?;
-            evaluator.output_vector("out", vector * scalar)
-        }
-        MyNodeTemplate::AddVector => {
-            let v1 = evaluator.input_vector("v1")?;
-            let v2 = evaluator.input_vector("v2")?;
-            evaluator.output_vector("out", v1 + v2)
-        }
-        MyNodeTemplate::SubtractVector => {
-            let v1 = evaluator.input_vector("v1")?;
-            let v2 = evaluator.input_vector("v2")?;
-            evaluator.output_vector("out", v1 - v2)
-        }
-        MyNodeTemplate::MakeVector => {
-            let x = evaluator.input_scalar("x")?;
-            let y = evaluator.input_scalar("y")?;
-            evaluator.output_vector("out", egui::vec2(x, y))
+// The library evaluates the graph by calling this trait on the data of each
+// node, after all of its dependencies have been evaluated. Here, the template
+// stored in the node data tells us what the node does.
+impl NodeEvaluatorTrait for MyNodeData {
+    type DataType = MyDataType;
+    type ValueType = MyValueType;
+    type Error = anyhow::Error;
+
+    fn evaluate(&self, ctx: &mut MyEvaluationContext<'_>) -> anyhow::Result<()> {
+        // The context gives access to the value of each input parameter:
+        // either the value computed for the connected output, or the inline
+        // value of the parameter when nothing is connected. Computed values
+        // are cached by the library, so each node is only evaluated once.
+        fn input_vector(ctx: &MyEvaluationContext<'_>, name: &str) -> anyhow::Result<egui::Vec2> {
+            ctx.input(name)?.try_to_vec2()
         }
-        MyNodeTemplate::MakeScalar => {
-            let value = evaluator.input_scalar("value")?;
-            evaluator.output_scalar("out", value)
+        fn input_scalar(ctx: &MyEvaluationContext<'_>, name: &str) -> anyhow::Result<f32> {
+            ctx.input(name)?.try_to_scalar()
         }
-    }
-}
 
-fn populate_output(
-    graph: &MyGraph,
-    outputs_cache: &mut OutputsCache,
-    node_id: NodeId,
-    param_name: &str,
-    value: MyValueType,
-) -> anyhow::Result<MyValueType> {
-    let output_id = graph[node_id].get_output(param_name)?;
-    outputs_cache.insert(output_id, value);
-    Ok(value)
-}
+        let out = match self.template {
+            MyNodeTemplate::AddScalar => {
+                let a = input_scalar(ctx, "A")?;
+                let b = input_scalar(ctx, "B")?;
+                MyValueType::Scalar { value: a + b }
+            }
+            MyNodeTemplate::SubtractScalar => {
+                let a = input_scalar(ctx, "A")?;
+                let b = input_scalar(ctx, "B")?;
+                MyValueType::Scalar { value: a - b }
+            }
+            MyNodeTemplate::VectorTimesScalar => {
+                let scalar = input_scalar(ctx, "scalar")?;
+                let vector = input_vector(ctx, "vector")?;
+                MyValueType::Vec2 {
+                    value: vector * scalar,
+                }
+            }
+            MyNodeTemplate::AddVector => {
+                let v1 = input_vector(ctx, "v1")?;
+                let v2 = input_vector(ctx, "v2")?;
+                MyValueType::Vec2 { value: v1 + v2 }
+            }
+            MyNodeTemplate::SubtractVector => {
+                let v1 = input_vector(ctx, "v1")?;
+                let v2 = input_vector(ctx, "v2")?;
+                MyValueType::Vec2 { value: v1 - v2 }
+            }
+            MyNodeTemplate::MakeVector => {
+                let x = input_scalar(ctx, "x")?;
+                let y = input_scalar(ctx, "y")?;
+                MyValueType::Vec2 {
+                    value: egui::vec2(x, y),
+                }
+            }
+            MyNodeTemplate::MakeScalar => {
+                let value = input_scalar(ctx, "value")?;
+                MyValueType::Scalar { value }
+            }
+        };
 
-// Evaluates the input value of
-fn evaluate_input(
-    graph: &MyGraph,
-    node_id: NodeId,
-    param_name: &str,
-    outputs_cache: &mut OutputsCache,
-) -> anyhow::Result<MyValueType> {
-    let input_id = graph[node_id].get_input(param_name)?;
-
-    // The output of another node is connected.
-    if let Some(other_output_id) = graph.connection(input_id) {
-        // The value was already computed due to the evaluation of some other
-        // node. We simply return value from the cache.
-        if let Some(other_value) = outputs_cache.get(&other_output_id) {
-            Ok(*other_value)
-        }
-        // This is the first time encountering this node, so we need to
-        // recursively evaluate it.
-        else {
-            // Calling this will populate the cache
-            evaluate_node(graph, graph[other_output_id].node, outputs_cache)?;
-
-            // Now that we know the value is cached, return it
-            Ok(*outputs_cache
-                .get(&other_output_id)
-                .expect("Cache should be populated"))
-        }
-    }
-    // No existing connection, take the inline value instead.
-    else {
-        Ok(graph[input_id].value)
+        // Every node in this example has a single output named "out". Note
+        // that this is just one possible semantic interpretation of the graphs,
+        // you can come up with your own evaluation semantics!
+        ctx.set_output("out", out)?;
+        Ok(())
     }
 }